@@ -0,0 +1,64 @@
+//! Compares how fast `Interpreter` and `Vm` run the same program.
+//!
+//! Run with `cargo bench --bench engines`.
+
+use std::time::{Duration, Instant};
+
+use piet::{assemble, Interpreter, Program, Vm};
+
+/// Counts down from a big number, which takes over a million steps.
+const COUNTDOWN: &str = "push 200000\nloop:\npush 1\nsub\ndup\njnz loop\nout num";
+
+fn run_interpreter(program: &Program) -> (String, u64) {
+    let mut interpreter = Interpreter::new(program.clone());
+    while let Some(result) = interpreter.step() {
+        result.unwrap();
+    }
+
+    (interpreter.output().to_string(), interpreter.steps())
+}
+
+fn run_vm(program: &Program) -> (String, u64) {
+    let mut vm = Vm::new(program);
+    vm.run(None);
+
+    (vm.output().to_string(), vm.steps())
+}
+
+/// The result of the fastest of a few runs, and how long it took.
+fn time<T>(run: impl Fn() -> T) -> (T, Duration) {
+    let mut fastest = None;
+
+    for _ in 0..3 {
+        let start = Instant::now();
+        let result = run();
+        let elapsed = start.elapsed();
+
+        if fastest.as_ref().is_none_or(|&(_, best)| elapsed < best) {
+            fastest = Some((result, elapsed));
+        }
+    }
+
+    fastest.unwrap()
+}
+
+fn main() {
+    let program = assemble(COUNTDOWN).unwrap();
+
+    let ((expected, steps), interpreter) = time(|| run_interpreter(&program));
+    let (result, vm) = time(|| run_vm(&program));
+    assert_eq!(result, (expected, steps), "the engines disagree");
+
+    let rate = |elapsed: Duration| steps as f64 / elapsed.as_secs_f64();
+    println!("{} steps", steps);
+    println!(
+        "interpreter: {:?} ({:.0} steps/s)",
+        interpreter,
+        rate(interpreter)
+    );
+    println!("vm:          {:?} ({:.0} steps/s)", vm, rate(vm));
+    println!(
+        "speedup:     {:.1}x",
+        interpreter.as_secs_f64() / vm.as_secs_f64()
+    );
+}
@@ -0,0 +1,266 @@
+use std::io::Write;
+
+use image::codecs::gif::GifEncoder;
+use image::{Delay, DynamicImage, Frame, Rgb, RgbImage};
+
+use crate::errors::*;
+use crate::font::{GLYPH_HEIGHT, GLYPH_WIDTH};
+use crate::interpreter::{CodelChooser, DirectionPointer, Interpreter};
+use crate::render::Canvas;
+
+const HIGHLIGHT_COLOR: Rgb<u8> = Rgb([0xff, 0x80, 0x00]);
+const TEXT_COLOR: Rgb<u8> = Rgb([0x00, 0x00, 0x00]);
+
+/// How many characters fit on a line of the sidebar.
+const SIDEBAR_COLUMNS: u32 = 14;
+
+/// Settings for `animate`.
+#[derive(Debug, Clone)]
+pub struct AnimationOptions {
+    /// How many pixels wide each codel is drawn.
+    pub scale: u32,
+    /// Draw a frame every this many steps.
+    pub every: u64,
+    /// How long each frame is shown, in milliseconds.
+    pub delay: u32,
+    /// Stop after this many steps, even if the program hasn't halted. Every
+    /// frame is kept in memory until the GIF is written, so this can't be
+    /// unlimited.
+    pub max_steps: u64,
+}
+
+impl Default for AnimationOptions {
+    fn default() -> Self {
+        AnimationOptions {
+            scale: 16,
+            every: 1,
+            delay: 100,
+            max_steps: 1000,
+        }
+    }
+}
+
+/// Run an interpreter, and write an animated GIF of its execution.
+///
+/// There is a frame for the start of the program, one for every `every`
+/// steps, and one for the state it ended in.
+///
+/// This fails if the GIF can't be written. Otherwise, it returns the result of
+/// running the program: if a step fails, the animation ends there, and the
+/// error is returned once the GIF is written.
+pub fn animate<W: Write>(
+    mut interpreter: Interpreter,
+    writer: W,
+    options: &AnimationOptions,
+) -> Result<Result<()>> {
+    let mut encoder = GifEncoder::new(writer);
+    let every = options.every.max(1);
+    let delay = Delay::from_numer_denom_ms(options.delay, 1);
+
+    let mut add_frame = |interpreter: &Interpreter| {
+        let image = DynamicImage::ImageRgb8(render_frame(interpreter, options.scale));
+        encoder.encode_frame(Frame::from_parts(image.into_rgba8(), 0, 0, delay))
+    };
+
+    add_frame(&interpreter)?;
+    let mut last_frame = 0;
+    let mut result = Ok(());
+
+    while interpreter.steps() < options.max_steps {
+        match interpreter.step() {
+            Some(Ok(_)) => {}
+            Some(Err(error)) => {
+                result = Err(error);
+                break;
+            }
+            None => break,
+        }
+
+        if interpreter.steps().is_multiple_of(every) {
+            add_frame(&interpreter)?;
+            last_frame = interpreter.steps();
+        }
+    }
+
+    if last_frame != interpreter.steps() || interpreter.is_halted() {
+        add_frame(&interpreter)?;
+    }
+
+    Ok(result)
+}
+
+/// Draw the current state of an interpreter.
+///
+/// The current color block is outlined, with an arrow pointing in the
+/// direction of the DP, and a dot on the side the CC points to. A sidebar
+/// shows the step count, the DP and CC, and the contents of the stack, with the
+/// top of the stack first.
+pub fn render_frame(interpreter: &Interpreter, scale: u32) -> RgbImage {
+    let scale = scale.max(1);
+    let text_size = (scale / 8).max(1);
+    let line_height = (GLYPH_HEIGHT + 2) * text_size;
+    let sidebar_width = (SIDEBAR_COLUMNS * (GLYPH_WIDTH + 1) + 2) * text_size;
+
+    let mut lines = vec![
+        format!("STEP {}", interpreter.steps()),
+        format!("DP {:?}", interpreter.dp()),
+        format!("CC {:?}", interpreter.cc()),
+    ];
+    if interpreter.is_halted() {
+        lines.push("HALTED".to_string());
+    }
+    lines.push(String::new());
+    lines.push(format!("STACK {}", interpreter.stack().len()));
+
+    let (program_width, program_height) = interpreter.program().size();
+    let min_height = (lines.len() as u32 + 1) * line_height;
+    let height = (program_height as u32 * scale).max(min_height);
+    let mut canvas = Canvas::with_margin(interpreter.program(), scale, sidebar_width, height);
+    let left = (program_width as u32 * scale + text_size) as i64;
+
+    let block = interpreter.program().color_block(interpreter.position());
+    canvas.outline(&block, (scale / 8).max(1), HIGHLIGHT_COLOR);
+    draw_pointers(&mut canvas, interpreter);
+
+    let available = (height / line_height) as usize;
+    let values = interpreter
+        .stack()
+        .iter()
+        .rev()
+        .map(|value| value.to_string());
+    let remaining = available.saturating_sub(lines.len());
+
+    if interpreter.stack().len() > remaining {
+        lines.extend(values.take(remaining.saturating_sub(1)));
+        lines.push("...".to_string());
+    } else {
+        lines.extend(values);
+    }
+
+    for (index, line) in lines.iter().enumerate() {
+        let top = (index as u32 * line_height + text_size) as i64;
+        let line = line
+            .chars()
+            .take(SIDEBAR_COLUMNS as usize)
+            .collect::<String>();
+        canvas.draw_text(left, top, &line, text_size, TEXT_COLOR);
+    }
+
+    canvas.into_image()
+}
+
+/// Draw an arrow for the DP, and a dot for the CC, on the current codel.
+fn draw_pointers(canvas: &mut Canvas, interpreter: &Interpreter) {
+    let (dx, dy) = match interpreter.dp() {
+        DirectionPointer::Up => (0, -1),
+        DirectionPointer::Right => (1, 0),
+        DirectionPointer::Down => (0, 1),
+        DirectionPointer::Left => (-1, 0),
+    };
+
+    // Turning left from the DP, so when it points right, left is up.
+    let (px, py) = match interpreter.cc() {
+        CodelChooser::Left => (dy, -dx),
+        CodelChooser::Right => (-dy, dx),
+    };
+
+    let scale = canvas.scale() as i64;
+    let (x, y) = canvas.center(interpreter.position());
+    let reach = scale * 2 / 5;
+
+    canvas.draw_arrow((x, y), (x + dx * reach, y + dy * reach), TEXT_COLOR);
+    canvas.mark(
+        (x + px * reach / 2, y + py * reach / 2),
+        (scale / 5) as u32,
+        HIGHLIGHT_COLOR,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::{Color, Hue::*, Lightness::*};
+    use crate::program::Program;
+
+    fn program() -> Program {
+        let lr = Color::Composite(Red, Light);
+        let nr = Color::Composite(Red, Normal);
+
+        Program::new((2, 1), vec![lr, nr])
+    }
+
+    #[test]
+    fn test_render_frame() {
+        let interpreter = Interpreter::new(program());
+        let image = render_frame(&interpreter, 16);
+
+        assert!(image.width() > 32);
+        // The current block is outlined.
+        assert_eq!(image.get_pixel(0, 0), &HIGHLIGHT_COLOR);
+        assert_eq!(image.get_pixel(16, 0), &Rgb([0xff, 0x00, 0x00]));
+    }
+
+    #[test]
+    fn test_animate() {
+        let options = AnimationOptions {
+            every: 2,
+            max_steps: 5,
+            ..AnimationOptions::default()
+        };
+
+        let mut gif = Vec::new();
+        animate(Interpreter::new(program()), &mut gif, &options)
+            .unwrap()
+            .unwrap();
+
+        let decoder = image::codecs::gif::GifDecoder::new(&gif[..]).unwrap();
+        let frames = image::AnimationDecoder::into_frames(decoder)
+            .collect_frames()
+            .unwrap();
+
+        // Steps 0, 2, 4, and the final state at step 5.
+        assert_eq!(frames.len(), 4);
+
+        // The program never halts, so it's cut off after the default number of
+        // steps.
+        let options = AnimationOptions {
+            scale: 1,
+            every: 100,
+            ..AnimationOptions::default()
+        };
+
+        let mut gif = Vec::new();
+        animate(Interpreter::new(program()), &mut gif, &options)
+            .unwrap()
+            .unwrap();
+
+        let decoder = image::codecs::gif::GifDecoder::new(&gif[..]).unwrap();
+        let frames = image::AnimationDecoder::into_frames(decoder)
+            .collect_frames()
+            .unwrap();
+
+        assert_eq!(frames.len(), 11);
+    }
+
+    #[test]
+    fn test_animate_error() {
+        use crate::dialect::Dialect;
+
+        let mut interpreter = Interpreter::new(Program::new((1, 1), vec![Color::White]));
+        interpreter.set_dialect(Dialect::STRICT);
+
+        let mut gif = Vec::new();
+        match animate(interpreter, &mut gif, &AnimationOptions::default()).unwrap() {
+            Err(Error(ErrorKind::StartOnWhite, _)) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+
+        let decoder = image::codecs::gif::GifDecoder::new(&gif[..]).unwrap();
+        let frames = image::AnimationDecoder::into_frames(decoder)
+            .collect_frames()
+            .unwrap();
+
+        // The start, and the state it stopped in.
+        assert_eq!(frames.len(), 2);
+    }
+}
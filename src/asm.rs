@@ -0,0 +1,454 @@
+use std::collections::HashMap;
+
+use crate::color::{Color, Hue, Lightness};
+use crate::command::Command;
+use crate::errors::*;
+use crate::program::Program;
+use crate::synth::{Cost, Synthesizer};
+
+/// The color that every row of code starts with.
+const START_COLOR: Color = Color::Composite(Hue::Red, Lightness::Light);
+
+/// Where a jump goes: a row created while parsing, or a label that may not
+/// have been defined yet, along with the line it was used on.
+#[derive(Debug, Clone)]
+enum Target {
+    Row(usize),
+    Label(String, usize),
+}
+
+/// How a row of code ends.
+#[derive(Debug, Clone)]
+enum End<T> {
+    Halt,
+    Jump(T),
+    /// Turn the DP by the value on top of the stack, which was popped by a
+    /// `Pointer` at the end of the row. 0 goes to the first target, 1 to the
+    /// second.
+    Branch(T, T),
+}
+
+/// A straight line of commands, which is laid out as one row of the image.
+#[derive(Debug, Clone)]
+struct Row<T> {
+    /// Each command, and the size of the block before it. That's only more
+    /// than 1 for `Push`.
+    commands: Vec<(Command, usize)>,
+    end: Option<End<T>>,
+}
+
+impl<T> Row<T> {
+    fn new() -> Self {
+        Row {
+            commands: Vec::new(),
+            end: None,
+        }
+    }
+}
+
+/// Assemble a program from a small stack assembly language.
+///
+/// Every line holds an instruction, a label like `loop:`, or both, and
+/// anything after a `;` is a comment. The instructions are:
+///
+/// - `push N`, for any number. Numbers are calculated with as few codels as
+///   `Synthesizer` can find, so big or negative numbers work too.
+/// - The other commands, written the same way as `Command::mnemonic`: `pop`,
+///   `dup`, `roll`, `add`, `sub`, `mul`, `div`, `mod`, `not`, `gt`, `switch`,
+///   `in num`, `in char`, `out num` and `out char`.
+/// - `jmp LABEL`.
+/// - `jz LABEL` and `jnz LABEL`, which pop a value and jump if it's zero, or
+///   not zero.
+/// - `pointer A, B`, which pops a value and goes to `A` if it's 0, or `B` if
+///   it's 1. Other values aren't supported.
+/// - `halt`. The program also halts after the last line.
+///
+/// The program is laid out with one row of codels for each straight line of
+/// code, and white paths for every jump.
+pub fn assemble(source: &str) -> Result<Program> {
+    let rows = parse(source)?;
+
+    Ok(layout(&rows))
+}
+
+fn parse(source: &str) -> Result<Vec<Row<usize>>> {
+    let mut rows = vec![Row::<Target>::new()];
+    let mut labels = HashMap::new();
+    let mut synthesizer = Synthesizer::new(Cost::Area);
+
+    for (index, line) in source.lines().enumerate() {
+        let number = index + 1;
+        let syntax = |message: String| -> Error { ErrorKind::Syntax(number, message).into() };
+
+        let line = line.split(';').next().unwrap_or("");
+        let mut words = line
+            .split(|c: char| c.is_whitespace() || c == ',' || c == '[' || c == ']')
+            .filter(|word| !word.is_empty())
+            .peekable();
+
+        if let Some(label) = words.peek().and_then(|word| word.strip_suffix(':')) {
+            let label = label.to_string();
+            words.next();
+
+            let current = rows.len() - 1;
+            let row = if rows[current].commands.is_empty() && rows[current].end.is_none() {
+                current
+            } else {
+                // Fall through into the labelled row.
+                rows[current]
+                    .end
+                    .get_or_insert(End::Jump(Target::Row(current + 1)));
+                rows.push(Row::new());
+                current + 1
+            };
+
+            if labels.insert(label.clone(), row).is_some() {
+                return Err(syntax(format!("label `{}` is defined twice", label)));
+            }
+        }
+
+        let word = match words.next() {
+            Some(word) => word,
+            None => continue,
+        };
+
+        // Code after a jump can only be reached through a label.
+        if rows.last().unwrap().end.is_some() {
+            rows.push(Row::new());
+        }
+
+        let current = rows.len() - 1;
+        let mut target = || match words.next() {
+            Some(label) => Ok(Target::Label(label.to_string(), number)),
+            None => Err(syntax(format!("`{}` needs a label", word))),
+        };
+
+        let (commands, end) = match word {
+            "push" => {
+                let value = match words.next().map(|value| value.parse::<i64>()) {
+                    Some(Ok(value)) => value,
+                    _ => return Err(syntax("`push` needs a number".to_string())),
+                };
+
+                (synthesizer.synthesize(value), None)
+            }
+            "jmp" => (vec![], Some(End::Jump(target()?))),
+            "jz" => (
+                vec![(Command::Not, 1), (Command::Pointer, 1)],
+                Some(End::Branch(Target::Row(current + 1), target()?)),
+            ),
+            "jnz" => (
+                vec![(Command::Not, 1), (Command::Not, 1), (Command::Pointer, 1)],
+                Some(End::Branch(Target::Row(current + 1), target()?)),
+            ),
+            "pointer" => {
+                let zero = target()?;
+                let one = target()?;
+                (vec![(Command::Pointer, 1)], Some(End::Branch(zero, one)))
+            }
+            "halt" => (vec![], Some(End::Halt)),
+            _ => {
+                let mnemonic = match word {
+                    "in" | "out" => format!("{} {}", word, words.next().unwrap_or("")),
+                    _ => word.to_string(),
+                };
+                let command = COMMANDS
+                    .iter()
+                    .copied()
+                    .find(|command| command.mnemonic() == mnemonic)
+                    .ok_or_else(|| syntax(format!("unknown instruction `{}`", mnemonic)))?;
+
+                (vec![(command, 1)], None)
+            }
+        };
+
+        if let Some(extra) = words.next() {
+            return Err(syntax(format!("unexpected `{}`", extra)));
+        }
+
+        let row = rows.last_mut().unwrap();
+        row.commands.extend(commands);
+        row.end = end;
+
+        // `jz` and `jnz` continue on the next row when they don't jump.
+        if let Some(End::Branch(Target::Row(_), _)) = row.end {
+            rows.push(Row::new());
+        }
+    }
+
+    let resolve = |target: Target| match target {
+        Target::Row(row) => Ok(row),
+        Target::Label(label, line) => labels.get(&label).copied().ok_or_else(|| {
+            Error::from(ErrorKind::Syntax(
+                line,
+                format!("unknown label `{}`", label),
+            ))
+        }),
+    };
+
+    rows.into_iter()
+        .map(|row| {
+            let end = match row.end {
+                None | Some(End::Halt) => End::Halt,
+                Some(End::Jump(target)) => End::Jump(resolve(target)?),
+                Some(End::Branch(zero, one)) => End::Branch(resolve(zero)?, resolve(one)?),
+            };
+
+            Ok(Row {
+                commands: row.commands,
+                end: Some(end),
+            })
+        })
+        .collect()
+}
+
+/// Every command that can be written directly, rather than through `push`,
+/// `jz` and so on.
+const COMMANDS: [Command; 15] = [
+    Command::Pop,
+    Command::Duplicate,
+    Command::Roll,
+    Command::Add,
+    Command::Subtract,
+    Command::Multiply,
+    Command::Divide,
+    Command::Mod,
+    Command::Not,
+    Command::Greater,
+    Command::Switch,
+    Command::IntIn,
+    Command::IntOut,
+    Command::CharIn,
+    Command::CharOut,
+];
+
+/// Lay out rows of code as an image.
+///
+/// Everything is black, except for:
+///
+/// - A colored codel at the top left, where the interpreter starts.
+/// - A row of color blocks for each row of code, one codel high, placed like a
+///   staircase so no two rows share any columns. Each row is entered from the
+///   left, and ends with a block that the interpreter can only leave
+///   downwards, to jump, or that leads into a trap with no exits, to halt.
+/// - For every jump, a white path that goes down from the end of the row, left
+///   along its own row at the bottom, up along its own column on the left, and
+///   right into the row it jumps to. These only ever turn clockwise, which is
+///   what the interpreter does when a slide is blocked. Paths can cross,
+///   because slides go straight through white.
+fn layout(rows: &[Row<usize>]) -> Program {
+    struct Jump {
+        /// The column the path goes down.
+        column: usize,
+        /// The first codel of the path, which the interpreter slides into.
+        top: usize,
+        target: usize,
+    }
+
+    let jump_count = rows
+        .iter()
+        .map(|row| match row.end {
+            Some(End::Jump(_)) => 1,
+            Some(End::Branch(_, _)) => 2,
+            _ => 0,
+        })
+        .sum::<usize>();
+
+    let mut jumps = Vec::new();
+    let mut x = 2 * jump_count + 3;
+    // The start of each row, the column of its last block, and its colors.
+    let mut placed = Vec::new();
+
+    for (index, row) in rows.iter().enumerate() {
+        let y = 3 * index;
+        let start = x;
+        let mut color = START_COLOR;
+        let mut blocks = Vec::new();
+
+        for &(command, size) in &row.commands {
+            blocks.push((color, size));
+            color = color.shifted(command.transition()).unwrap();
+        }
+        blocks.push((color, 1));
+
+        let tail = start + blocks.iter().map(|&(_, size)| size).sum::<usize>() - 1;
+
+        x = match row.end {
+            Some(End::Jump(target)) => {
+                jumps.push(Jump {
+                    column: tail,
+                    top: y + 1,
+                    target,
+                });
+                tail + 2
+            }
+            Some(End::Branch(zero, one)) => {
+                // 0 keeps the DP pointing right, and 1 turns it down.
+                jumps.push(Jump {
+                    column: tail + 1,
+                    top: y,
+                    target: zero,
+                });
+                jumps.push(Jump {
+                    column: tail,
+                    top: y + 1,
+                    target: one,
+                });
+                tail + 3
+            }
+            _ => tail + 4,
+        };
+
+        placed.push((start, tail, blocks));
+    }
+
+    let width = x;
+    let height = 3 * rows.len() + 2 * jumps.len();
+    let mut codels = vec![Color::Black; width * height];
+    let mut set = |(x, y): (usize, usize), color: Color| codels[y * width + x] = color;
+
+    set((0, 0), START_COLOR);
+    for x in 1..placed[0].0 {
+        set((x, 0), Color::White);
+    }
+
+    for (index, ((start, tail, blocks), row)) in placed.iter().zip(rows).enumerate() {
+        let y = 3 * index;
+        let mut x = *start;
+
+        for &(color, size) in blocks {
+            for _ in 0..size {
+                set((x, y), color);
+                x += 1;
+            }
+        }
+
+        if let Some(End::Halt) = row.end {
+            // The trap can't be left in any direction, because the codel it's
+            // entered from is never on one of its edges.
+            set((tail + 1, y), Color::White);
+            set((tail + 2, y), START_COLOR);
+            set((tail + 2, y + 1), START_COLOR);
+            set((tail + 1, y + 1), START_COLOR);
+        }
+    }
+
+    for (index, jump) in jumps.iter().enumerate() {
+        let bottom = 3 * rows.len() + 2 * index;
+        let left = 2 + 2 * index;
+        let target_y = 3 * jump.target;
+
+        for y in jump.top..=bottom {
+            set((jump.column, y), Color::White);
+        }
+        for x in left..=jump.column {
+            set((x, bottom), Color::White);
+        }
+        for y in target_y..=bottom {
+            set((left, y), Color::White);
+        }
+        for x in left..placed[jump.target].0 {
+            set((x, target_y), Color::White);
+        }
+    }
+
+    Program::new((width, height), codels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Interpreter;
+
+    fn run(source: &str, input: &str) -> String {
+        let mut interpreter = Interpreter::new(assemble(source).unwrap());
+        interpreter.feed_input(input);
+
+        for _ in 0..10_000 {
+            match interpreter.step() {
+                Some(result) => {
+                    result.unwrap();
+                }
+                None => return interpreter.output().to_string(),
+            }
+        }
+
+        panic!("the program didn't halt");
+    }
+
+    #[test]
+    fn test_assemble_straight_line() {
+        let source = "
+            push 72     ; H
+            out char
+            push 105
+            out char
+            push 0
+            out num
+            push -12
+            out num
+        ";
+
+        assert_eq!(run(source, ""), "Hi0-12");
+    }
+
+    #[test]
+    fn test_assemble_loop() {
+        let source = "
+            in num
+        loop:
+            dup
+            out num
+            push 1
+            sub
+            dup
+            jnz loop
+            push 33
+            out char
+        ";
+
+        assert_eq!(run(source, "3"), "321!");
+        assert_eq!(run(source, "1"), "1!");
+    }
+
+    #[test]
+    fn test_assemble_branches() {
+        let source = "
+            in num
+            jz zero
+            in num
+            pointer one, two
+        zero:
+            push 48
+            out char
+            halt
+        one:
+            push 49
+            out char
+            jmp zero
+        two:
+            push 50
+            out char
+        ";
+
+        assert_eq!(run(source, "0"), "0");
+        assert_eq!(run(source, "5 0"), "10");
+        assert_eq!(run(source, "5 1"), "2");
+    }
+
+    #[test]
+    fn test_assemble_errors() {
+        let error = assemble("push 1\njmp nowhere").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "syntax error on line 2: unknown label `nowhere`"
+        );
+
+        assert!(assemble("a:\na:").is_err());
+        assert!(assemble("push").is_err());
+        assert!(assemble("push 1 2").is_err());
+        assert!(assemble("frobnicate").is_err());
+        assert!(assemble("out").is_err());
+        assert!(assemble("").is_ok());
+    }
+}
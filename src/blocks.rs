@@ -0,0 +1,470 @@
+use std::collections::HashSet;
+use std::ops::Index;
+
+use crate::color::Color;
+use crate::command::Command;
+use crate::dialect::{Dialect, StartOnWhite};
+use crate::errors::*;
+use crate::interpreter::{CodelChooser, DirectionPointer};
+use crate::program::Program;
+use crate::trace::Step;
+use crate::util::Coords;
+
+/// An index into `Blocks`.
+pub type BlockId = usize;
+
+/// A contiguous area of codels with the same color.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    color: Color,
+    codels: Vec<Coords>,
+}
+
+impl Block {
+    pub fn color(&self) -> Color {
+        self.color
+    }
+
+    /// The codels in this block, row by row.
+    pub fn codels(&self) -> &[Coords] {
+        &self.codels
+    }
+
+    pub fn size(&self) -> usize {
+        self.codels.len()
+    }
+
+    /// The first codel of the block, reading row by row. This is a convenient
+    /// way to refer to a block.
+    pub fn origin(&self) -> Coords {
+        self.codels[0]
+    }
+
+    /// The codel that the interpreter leaves this block from, given the
+    /// direction of the DP and CC.
+    pub fn exit(&self, dp: DirectionPointer, cc: CodelChooser) -> Coords {
+        use crate::interpreter::CodelChooser as CC;
+        use crate::interpreter::DirectionPointer as DP;
+
+        fn x_key(&(x, _): &Coords) -> usize {
+            x
+        }
+        fn y_key(&(_, y): &Coords) -> usize {
+            y
+        }
+
+        let edge = if dp == DP::Up || dp == DP::Down {
+            let iter = self.codels.iter().copied();
+
+            let (_, farthest_y) = match dp {
+                DP::Up => iter.min_by_key(y_key),
+                DP::Down => iter.max_by_key(y_key),
+                _ => unreachable!(),
+            }
+            .unwrap(); // We can unwrap here because a block is never empty.
+
+            self.codels
+                .iter()
+                .filter(|&&(_x, y)| y == farthest_y)
+                .copied()
+                .collect::<Vec<_>>()
+        } else if dp == DP::Left || dp == DP::Right {
+            let iter = self.codels.iter().copied();
+
+            let (farthest_x, _) = match dp {
+                DP::Left => iter.min_by_key(x_key),
+                DP::Right => iter.max_by_key(x_key),
+                _ => unreachable!(),
+            }
+            .unwrap(); // Same as above.
+
+            self.codels
+                .iter()
+                .filter(|&&(x, _y)| x == farthest_x)
+                .copied()
+                .collect::<Vec<_>>()
+        } else {
+            unreachable!()
+        }
+        .into_iter();
+
+        // The CC is relative to the DP: when facing right, 'left' is up.
+        match dp {
+            DP::Left => match cc {
+                CC::Right => edge.min_by_key(y_key),
+                CC::Left => edge.max_by_key(y_key),
+            },
+            DP::Right => match cc {
+                CC::Right => edge.max_by_key(y_key),
+                CC::Left => edge.min_by_key(y_key),
+            },
+            DP::Up => match cc {
+                CC::Right => edge.max_by_key(x_key),
+                CC::Left => edge.min_by_key(x_key),
+            },
+            DP::Down => match cc {
+                CC::Right => edge.min_by_key(x_key),
+                CC::Left => edge.max_by_key(x_key),
+            },
+        }
+        .unwrap() // See above.
+    }
+}
+
+/// All the color blocks in a program, and which block each codel belongs to.
+///
+/// Blocks are numbered in the order their first codel appears, reading row by
+/// row, so the block containing `(0, 0)` is always block 0.
+#[derive(Debug, Clone)]
+pub struct Blocks {
+    size: Coords,
+    blocks: Vec<Block>,
+    labels: Vec<BlockId>,
+}
+
+impl Blocks {
+    pub fn new(program: &Program) -> Self {
+        let size = program.size();
+        let (width, height) = size;
+        let mut blocks = Vec::new();
+        let mut labels = vec![usize::MAX; width * height];
+
+        for (start, color) in program.codels() {
+            if labels[start.1 * width + start.0] != usize::MAX {
+                continue;
+            }
+
+            let id = blocks.len();
+            let mut codels = Vec::new();
+            let mut pending = vec![start];
+            labels[start.1 * width + start.0] = id;
+
+            while let Some(coords) = pending.pop() {
+                codels.push(coords);
+
+                let (x, y) = coords;
+                let neighbors = [
+                    x.checked_sub(1).map(|x| (x, y)),
+                    y.checked_sub(1).map(|y| (x, y)),
+                    Some((x + 1, y)),
+                    Some((x, y + 1)),
+                ];
+
+                for (nx, ny) in neighbors.iter().filter_map(|&c| c) {
+                    if nx < width
+                        && ny < height
+                        && labels[ny * width + nx] == usize::MAX
+                        && program.get((nx, ny)) == Some(color)
+                    {
+                        labels[ny * width + nx] = id;
+                        pending.push((nx, ny));
+                    }
+                }
+            }
+
+            codels.sort_by_key(|&(x, y)| (y, x));
+            blocks.push(Block { color, codels });
+        }
+
+        Blocks {
+            size,
+            blocks,
+            labels,
+        }
+    }
+
+    /// The block that a codel belongs to.
+    pub fn id_at(&self, coords: Coords) -> Option<BlockId> {
+        let (x, y) = coords;
+        let (width, height) = self.size;
+
+        if x >= width || y >= height {
+            None
+        } else {
+            Some(self.labels[y * width + x])
+        }
+    }
+
+    pub fn get(&self, id: BlockId) -> Option<&Block> {
+        self.blocks.get(id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (BlockId, &Block)> {
+        self.blocks.iter().enumerate()
+    }
+
+    /// The move the interpreter makes from the codel at `from`, with the DP
+    /// and CC pointing the given ways: out of its color block, through any
+    /// white codels, and into the next color block.
+    ///
+    /// The `next_dp` and `next_cc` of the step are the DP and CC after moving,
+    /// before the command is executed. This returns `None` if there's no way
+    /// out, which halts the program.
+    pub fn step(
+        &self,
+        from: Coords,
+        mut dp: DirectionPointer,
+        mut cc: CodelChooser,
+        dialect: &Dialect,
+    ) -> Result<Option<Step>> {
+        let color = match self.color_at(from) {
+            Some(color) => color,
+            None => return Ok(None),
+        };
+        let mut step = Step {
+            from,
+            exit: from,
+            to: from,
+            dp,
+            cc,
+            command: None,
+            next_dp: dp,
+            next_cc: cc,
+            retries: 0,
+            slide: 0,
+        };
+
+        let next = if color == Color::White {
+            // Only the start can be white.
+            match dialect.start_on_white {
+                StartOnWhite::Slide => self.slide(from, &mut dp, &mut cc, &mut step, dialect),
+                StartOnWhite::Halt => None,
+                StartOnWhite::Error => return Err(ErrorKind::StartOnWhite.into()),
+            }
+        } else {
+            match self.leave(from, &mut dp, &mut cc, &mut step) {
+                Some((next, Color::White)) => {
+                    self.slide(next, &mut dp, &mut cc, &mut step, dialect)
+                }
+                Some((next, next_color)) => {
+                    step.command =
+                        Color::transition(color, next_color).map(Command::from_transition);
+                    Some(next)
+                }
+                None => None,
+            }
+        };
+
+        Ok(next.map(|next| Step {
+            to: next,
+            next_dp: dp,
+            next_cc: cc,
+            ..step
+        }))
+    }
+
+    /// Find the codel that the interpreter moves to from the color block at
+    /// `from`, trying every combination of DP and CC in turn.
+    fn leave(
+        &self,
+        from: Coords,
+        dp: &mut DirectionPointer,
+        cc: &mut CodelChooser,
+        step: &mut Step,
+    ) -> Option<(Coords, Color)> {
+        let block = &self[self.id_at(from)?];
+
+        for attempt in 0..8 {
+            let exit = block.exit(*dp, *cc);
+
+            match self.neighbor(exit, *dp) {
+                Some((_, Color::Black)) | None => {}
+                next => {
+                    step.exit = exit;
+                    step.dp = *dp;
+                    step.cc = *cc;
+
+                    return next;
+                }
+            }
+
+            step.retries += 1;
+
+            if attempt % 2 == 0 {
+                cc.switch();
+            } else {
+                dp.rotate_clockwise();
+            }
+        }
+
+        None
+    }
+
+    /// Slide across white codels in the direction of the DP, starting at
+    /// `start`.
+    ///
+    /// Whenever the slide is blocked, the CC is switched and the DP is rotated
+    /// clockwise. If that leads back to a codel and direction that was seen
+    /// before, or it's blocked more often than the dialect allows, there's no
+    /// way out, and this returns `None`.
+    fn slide(
+        &self,
+        start: Coords,
+        dp: &mut DirectionPointer,
+        cc: &mut CodelChooser,
+        step: &mut Step,
+        dialect: &Dialect,
+    ) -> Option<Coords> {
+        let limit = dialect.white_retry_limit;
+        let mut visited = HashSet::new();
+        let mut position = start;
+        let mut retries = 0;
+        step.slide += 1;
+
+        loop {
+            if !visited.insert((position, *dp)) || limit.is_some_and(|limit| retries > limit) {
+                return None;
+            }
+
+            match self.neighbor(position, *dp) {
+                Some((next, Color::White)) => {
+                    position = next;
+                    step.slide += 1;
+                }
+                Some((next, color)) if color != Color::Black => return Some(next),
+                _ => {
+                    retries += 1;
+                    step.retries += 1;
+                    cc.switch();
+                    dp.rotate_clockwise();
+                }
+            }
+        }
+    }
+
+    /// The codel next to `coords` in the direction of the DP, if it's on the
+    /// image.
+    fn neighbor(&self, coords: Coords, dp: DirectionPointer) -> Option<(Coords, Color)> {
+        let next = dp.offset(coords)?;
+
+        self.color_at(next).map(|color| (next, color))
+    }
+
+    fn color_at(&self, coords: Coords) -> Option<Color> {
+        self.id_at(coords).map(|id| self.blocks[id].color)
+    }
+}
+
+impl Index<BlockId> for Blocks {
+    type Output = Block;
+
+    fn index(&self, id: BlockId) -> &Block {
+        &self.blocks[id]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_blocks_new() {
+        use crate::color::Color::White as W;
+        use crate::color::Color::Black as B;
+
+        let program = Program::new((4, 3), vec![
+            W, W, B, W,
+            B, W, B, W,
+            B, B, B, W,
+        ]);
+        let blocks = Blocks::new(&program);
+
+        assert_eq!(blocks.len(), 3);
+        assert_eq!(blocks[0].codels(), &[(0, 0), (1, 0), (1, 1)]);
+        assert_eq!(blocks[1].color(), B);
+        assert_eq!(blocks[1].size(), 6);
+        assert_eq!(blocks[1].origin(), (2, 0));
+        assert_eq!(blocks[2].codels(), &[(3, 0), (3, 1), (3, 2)]);
+
+        assert_eq!(blocks.id_at((1, 1)), Some(0));
+        assert_eq!(blocks.id_at((0, 2)), Some(1));
+        assert_eq!(blocks.id_at((3, 2)), Some(2));
+        assert_eq!(blocks.id_at((4, 0)), None);
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_block_exit() {
+        use crate::color::Color::White as W;
+        use crate::color::Color::Black as B;
+        use crate::interpreter::{CodelChooser as CC, DirectionPointer as DP};
+
+        // A plus sign, with its arms at different distances from the center.
+        let program = Program::new((4, 4), vec![
+            B, W, B, B,
+            W, W, W, W,
+            B, W, B, B,
+            B, W, B, B,
+        ]);
+        let blocks = Blocks::new(&program);
+        let block = &blocks[blocks.id_at((1, 1)).unwrap()];
+
+        assert_eq!(block.exit(DP::Right, CC::Left), (3, 1));
+        assert_eq!(block.exit(DP::Down, CC::Left), (1, 3));
+        assert_eq!(block.exit(DP::Left, CC::Right), (0, 1));
+        assert_eq!(block.exit(DP::Up, CC::Right), (1, 0));
+
+        let square = Program::new((2, 2), vec![W, W, W, W]);
+        let blocks = Blocks::new(&square);
+
+        assert_eq!(blocks[0].exit(DP::Right, CC::Left), (1, 0));
+        assert_eq!(blocks[0].exit(DP::Right, CC::Right), (1, 1));
+        assert_eq!(blocks[0].exit(DP::Down, CC::Left), (1, 1));
+        assert_eq!(blocks[0].exit(DP::Down, CC::Right), (0, 1));
+        assert_eq!(blocks[0].exit(DP::Left, CC::Left), (0, 1));
+        assert_eq!(blocks[0].exit(DP::Left, CC::Right), (0, 0));
+        assert_eq!(blocks[0].exit(DP::Up, CC::Left), (0, 0));
+        assert_eq!(blocks[0].exit(DP::Up, CC::Right), (1, 0));
+    }
+
+    #[test]
+    fn test_blocks_step() {
+        use crate::color::{Hue::*, Lightness::*};
+        use crate::interpreter::{CodelChooser as CC, DirectionPointer as DP};
+
+        let lr = Color::Composite(Red, Light);
+        let nr = Color::Composite(Red, Normal);
+        let program = Program::new((4, 1), vec![lr, nr, Color::White, lr]);
+        let blocks = Blocks::new(&program);
+        let dialect = Dialect::default();
+
+        let step = blocks
+            .step((0, 0), DP::Right, CC::Left, &dialect)
+            .unwrap()
+            .unwrap();
+        assert_eq!(step.to, (1, 0));
+        assert_eq!(step.command, Some(Command::Push));
+
+        // Sliding through white doesn't execute anything.
+        let step = blocks
+            .step((1, 0), DP::Right, CC::Left, &dialect)
+            .unwrap()
+            .unwrap();
+        assert_eq!((step.exit, step.to), ((1, 0), (3, 0)));
+        assert_eq!((step.command, step.slide), (None, 1));
+
+        // Blocked on the right, so it turns around.
+        let step = blocks
+            .step((3, 0), DP::Right, CC::Left, &dialect)
+            .unwrap()
+            .unwrap();
+        assert_eq!(step.to, (1, 0));
+        assert_eq!((step.dp, step.retries), (DP::Left, 4));
+
+        let program = Program::new((1, 1), vec![lr]);
+        let blocks = Blocks::new(&program);
+        assert_eq!(
+            blocks.step((0, 0), DP::Right, CC::Left, &dialect).unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,413 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::Write;
+
+use crate::blocks::{BlockId, Blocks};
+use crate::color::{Color, Lightness};
+use crate::command::Command;
+use crate::dialect::Dialect;
+use crate::interpreter::{CodelChooser, DirectionPointer};
+use crate::program::Program;
+
+/// Where the interpreter is, as far as control flow is concerned: the color
+/// block, and the direction of the DP and CC.
+pub type State = (BlockId, DirectionPointer, CodelChooser);
+
+/// A move from one state to another.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edge {
+    /// The state after the move, and after the command was executed.
+    pub to: State,
+    /// The direction pointer when leaving the color block.
+    pub dp: DirectionPointer,
+    /// The codel chooser when leaving the color block.
+    pub cc: CodelChooser,
+    /// The command that the move executes, if any.
+    pub command: Option<Command>,
+    /// For `Pointer` and `Switch`, which depend on the value on top of the
+    /// stack: how many times the DP was rotated clockwise, or the CC switched.
+    /// Every possible outcome gets its own edge.
+    pub branch: Option<u8>,
+    /// How many times the interpreter is blocked, and has to change the DP or
+    /// CC, before it can move.
+    pub retries: u32,
+    /// How many white codels are passed through.
+    pub slide: usize,
+}
+
+/// The control flow graph of a program.
+///
+/// It contains every state that can be reached from the start of the program,
+/// assuming that `Pointer` and `Switch` can turn any way. A state without
+/// edges halts the program.
+#[derive(Debug, Clone)]
+pub struct Cfg {
+    blocks: Blocks,
+    dialect: Dialect,
+    states: Vec<State>,
+    indices: HashMap<State, usize>,
+    edges: Vec<Vec<Edge>>,
+}
+
+impl Cfg {
+    pub fn new(program: &Program) -> Self {
+        Cfg::with_dialect(program, Dialect::default())
+    }
+
+    /// Builds the graph of a program, moving between color blocks the way the
+    /// dialect does. If it doesn't slide out of a white codel at the start,
+    /// the entry has no edges.
+    pub fn with_dialect(program: &Program, dialect: Dialect) -> Self {
+        let blocks = Blocks::new(program);
+        let mut cfg = Cfg {
+            blocks,
+            dialect,
+            states: Vec::new(),
+            indices: HashMap::new(),
+            edges: Vec::new(),
+        };
+
+        if cfg.blocks.is_empty() {
+            return cfg;
+        }
+
+        let mut pending = VecDeque::new();
+        pending.push_back(cfg.entry());
+        cfg.add_state(cfg.entry());
+
+        while let Some(state) = pending.pop_front() {
+            let edges = successors(&cfg.blocks, &cfg.dialect, state);
+
+            for edge in &edges {
+                if !cfg.indices.contains_key(&edge.to) {
+                    cfg.add_state(edge.to);
+                    pending.push_back(edge.to);
+                }
+            }
+
+            let index = cfg.indices[&state];
+            cfg.edges[index] = edges;
+        }
+
+        cfg
+    }
+
+    fn add_state(&mut self, state: State) {
+        self.indices.insert(state, self.states.len());
+        self.states.push(state);
+        self.edges.push(Vec::new());
+    }
+
+    /// The state the interpreter starts in.
+    pub fn entry(&self) -> State {
+        (0, DirectionPointer::Right, CodelChooser::Left)
+    }
+
+    pub fn blocks(&self) -> &Blocks {
+        &self.blocks
+    }
+
+    pub fn dialect(&self) -> &Dialect {
+        &self.dialect
+    }
+
+    /// Every reachable state, in the order they were found, starting with the
+    /// entry.
+    pub fn states(&self) -> &[State] {
+        &self.states
+    }
+
+    /// The position of a state in `states`, if it's reachable.
+    pub fn index(&self, state: State) -> Option<usize> {
+        self.indices.get(&state).copied()
+    }
+
+    /// The moves out of a state. This is empty for states that halt, and for
+    /// states that aren't reachable.
+    pub fn edges(&self, state: State) -> &[Edge] {
+        self.index(state)
+            .map(|index| &self.edges[index][..])
+            .unwrap_or(&[])
+    }
+
+    /// The positions of the states that start a straight line of moves: the
+    /// entry, every outcome of a branch, and states with more or fewer than
+    /// one way in. Every other state is only ever reached from the one before
+    /// it.
+    pub fn leaders(&self) -> Vec<usize> {
+        let mut incoming = vec![0; self.states.len()];
+        let mut leaders = vec![false; self.states.len()];
+
+        if let Some(entry) = leaders.first_mut() {
+            *entry = true;
+        }
+
+        for edges in &self.edges {
+            for edge in edges {
+                let to = self.indices[&edge.to];
+                incoming[to] += 1;
+                leaders[to] |= edges.len() > 1;
+            }
+        }
+
+        (0..self.states.len())
+            .filter(|&index| leaders[index] || incoming[index] != 1)
+            .collect()
+    }
+
+    /// The blocks that the interpreter can ever be in.
+    pub fn reachable_blocks(&self) -> HashSet<BlockId> {
+        self.states.iter().map(|&(block, _, _)| block).collect()
+    }
+
+    /// Write the graph in Graphviz's DOT language.
+    ///
+    /// Every state is a box filled with the color of its block. Edges that
+    /// depend on data, from `Pointer` and `Switch`, are dashed.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+
+        writeln!(dot, "digraph piet {{").unwrap();
+        writeln!(
+            dot,
+            "    node [shape=box, style=filled, fontname=monospace];"
+        )
+        .unwrap();
+        writeln!(dot, "    start [shape=point];").unwrap();
+        writeln!(dot, "    halt [shape=doublecircle, style=solid];").unwrap();
+        writeln!(dot, "    start -> s0;").unwrap();
+
+        for (index, &(id, dp, cc)) in self.states.iter().enumerate() {
+            let block = &self.blocks[id];
+            let (r, g, b) = block.color().to_rgb();
+            let font = match block.color() {
+                Color::Black | Color::Composite(_, Lightness::Dark) => "white",
+                _ => "black",
+            };
+            let (x, y) = block.origin();
+
+            writeln!(
+                dot,
+                "    s{} [label=\"{} ({}, {})\\n{} {}\", fillcolor=\"#{:02x}{:02x}{:02x}\", fontcolor={}];",
+                index,
+                id,
+                x,
+                y,
+                dp,
+                cc,
+                r,
+                g,
+                b,
+                font
+            ).unwrap();
+        }
+
+        for (index, edges) in self.edges.iter().enumerate() {
+            if edges.is_empty() {
+                writeln!(dot, "    s{} -> halt;", index).unwrap();
+            }
+
+            let (id, _, _) = self.states[index];
+
+            for edge in edges {
+                let mut attributes = vec![];
+
+                if let Some(label) = self.edge_label(id, edge) {
+                    attributes.push(format!("label=\"{}\"", label));
+                }
+                if edge.branch.is_some() {
+                    attributes.push("style=dashed".to_string());
+                }
+
+                write!(dot, "    s{} -> s{}", index, self.indices[&edge.to]).unwrap();
+                if !attributes.is_empty() {
+                    write!(dot, " [{}]", attributes.join(", ")).unwrap();
+                }
+                writeln!(dot, ";").unwrap();
+            }
+        }
+
+        writeln!(dot, "}}").unwrap();
+
+        dot
+    }
+
+    fn edge_label(&self, from: BlockId, edge: &Edge) -> Option<String> {
+        match (edge.command, edge.branch) {
+            (Some(Command::Push), _) => Some(format!("push {}", self.blocks[from].size())),
+            (Some(command), Some(branch)) => Some(format!("{} {}", command.mnemonic(), branch)),
+            (Some(command), None) => Some(command.mnemonic().to_string()),
+            (None, _) => None,
+        }
+    }
+}
+
+/// Every move out of a state.
+fn successors(blocks: &Blocks, dialect: &Dialect, state: State) -> Vec<Edge> {
+    let (id, dp, cc) = state;
+    let from = blocks[id].origin();
+
+    let step = match blocks.step(from, dp, cc, dialect) {
+        Ok(Some(step)) => step,
+        _ => return vec![],
+    };
+    let to = match blocks.id_at(step.to) {
+        Some(to) => to,
+        None => return vec![],
+    };
+
+    let (dp, cc) = (step.next_dp, step.next_cc);
+    let edge = Edge {
+        to: state,
+        dp: step.dp,
+        cc: step.cc,
+        command: step.command,
+        branch: None,
+        retries: step.retries,
+        slide: step.slide,
+    };
+
+    match edge.command {
+        Some(Command::Pointer) => (0..4)
+            .map(|turns| {
+                let mut dp = dp;
+                for _ in 0..turns {
+                    dp.rotate_clockwise();
+                }
+
+                Edge {
+                    to: (to, dp, cc),
+                    branch: Some(turns),
+                    ..edge.clone()
+                }
+            })
+            .collect(),
+        Some(Command::Switch) => (0..2)
+            .map(|switches| {
+                let mut cc = cc;
+                if switches == 1 {
+                    cc.switch();
+                }
+
+                Edge {
+                    to: (to, dp, cc),
+                    branch: Some(switches),
+                    ..edge.clone()
+                }
+            })
+            .collect(),
+        _ => vec![Edge {
+            to: (to, dp, cc),
+            ..edge
+        }],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::{Hue::*, Lightness::*};
+    use crate::interpreter::Interpreter;
+
+    const B: Color = Color::Black;
+    const W: Color = Color::White;
+    const LR: Color = Color::Composite(Red, Light);
+    const NR: Color = Color::Composite(Red, Normal);
+    const DB: Color = Color::Composite(Blue, Dark);
+    const LY: Color = Color::Composite(Yellow, Light);
+    const NY: Color = Color::Composite(Yellow, Normal);
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_cfg_straight_line() {
+        use crate::interpreter::{CodelChooser as CC, DirectionPointer as DP};
+
+        let program = Program::new((5, 1), vec![LR, NR, W, LR, B]);
+        let cfg = Cfg::new(&program);
+
+        let edges = cfg.edges((0, DP::Right, CC::Left));
+        assert_eq!(edges.len(), 1);
+        assert_eq!(edges[0].command, Some(Command::Push));
+        assert_eq!(edges[0].to, (1, DP::Right, CC::Left));
+
+        // Sliding through white doesn't execute anything.
+        let edges = cfg.edges((1, DP::Right, CC::Left));
+        assert_eq!(edges[0].command, None);
+        assert_eq!(edges[0].slide, 1);
+        assert_eq!(edges[0].to, (3, DP::Right, CC::Left));
+
+        // Blocked on the right, so the interpreter turns around.
+        let edges = cfg.edges((3, DP::Right, CC::Left));
+        assert_eq!(edges[0].retries, 4);
+        assert_eq!(edges[0].dp, DP::Left);
+
+        assert!(cfg.to_dot().contains("s0 -> s1 [label=\"push 1\"];"));
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_cfg_branches() {
+        // Read a number, and turn the DP by it with `Pointer`.
+        let program = Program::new((4, 2), vec![
+            LR, DB, LY, B,
+            B,  B,  NY, B,
+        ]);
+        let cfg = Cfg::new(&program);
+
+        let edges = cfg.edges(cfg.states()[1]);
+        assert_eq!(edges.len(), 4);
+        assert!(edges.iter().all(|edge| edge.command == Some(Command::Pointer)));
+        assert_eq!(
+            edges.iter().map(|edge| edge.branch).collect::<Vec<_>>(),
+            vec![Some(0), Some(1), Some(2), Some(3)]
+        );
+        assert_eq!(cfg.reachable_blocks().len(), 4);
+        assert!(cfg.to_dot().contains("style=dashed"));
+
+        // Every step the interpreter takes is an edge in the graph.
+        for input in &["0", "1", "2", "3"] {
+            let mut interpreter = Interpreter::new(program.clone());
+            interpreter.feed_input(input);
+
+            for _ in 0..20 {
+                let blocks = cfg.blocks();
+                let before = (
+                    blocks.id_at(interpreter.position()).unwrap(),
+                    interpreter.dp(),
+                    interpreter.cc(),
+                );
+
+                let step = match interpreter.step() {
+                    Some(step) => step.unwrap(),
+                    None => break,
+                };
+                let after = (blocks.id_at(step.to).unwrap(), step.next_dp, step.next_cc);
+
+                assert!(cfg.edges(before).iter().any(|edge| edge.to == after));
+            }
+        }
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_cfg_dialect() {
+        use crate::dialect::StartOnWhite;
+
+        // Starting on white, sliding right is blocked by the edge, so it
+        // turns down.
+        let program = Program::new((2, 2), vec![
+            W,  W,
+            B,  NR,
+        ]);
+        let entry = Cfg::new(&program).entry();
+
+        assert_eq!(Cfg::new(&program).edges(entry).len(), 1);
+        assert!(Cfg::with_dialect(&program, Dialect::STRICT).edges(entry).is_empty());
+
+        let limited = Dialect {
+            white_retry_limit: Some(0),
+            start_on_white: StartOnWhite::Slide,
+            ..Dialect::NPIET
+        };
+        assert!(Cfg::with_dialect(&program, limited).edges(entry).is_empty());
+    }
+}
@@ -1,3 +1,5 @@
+use std::fmt;
+
 /// A color's hue.
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Hue {
@@ -10,6 +12,16 @@ pub enum Hue {
 }
 
 impl Hue {
+    /// Every hue, in the order of the hue cycle.
+    pub const ALL: [Hue; 6] = [
+        Hue::Red,
+        Hue::Yellow,
+        Hue::Green,
+        Hue::Cyan,
+        Hue::Blue,
+        Hue::Magenta,
+    ];
+
     /// Calculate the change in hue between two hues.
     ///
     /// # Examples
@@ -34,6 +46,9 @@ pub enum Lightness {
 }
 
 impl Lightness {
+    /// Every lightness, in the order of the lightness cycle.
+    pub const ALL: [Lightness; 3] = [Lightness::Light, Lightness::Normal, Lightness::Dark];
+
     /// Calculate the change in lightness between two lightness values.
     ///
     /// # Examples
@@ -100,9 +115,72 @@ impl Color {
             0xffffff => White,
             0x000000 => Black,
 
+            // `LoadOptions` can choose something else when loading images.
             _ => White,
-            // TODO: maybe make the fallback color configurable? Or even find
-            // the nearest color, and create a `Composite` based on that.
+        }
+    }
+
+    /// The color in the palette that's closest to an RGB triplet. This is the
+    /// same as `from_rgb` for colors that are in the palette.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use piet::{Color, Hue::*, Lightness::*};
+    ///
+    /// assert_eq!(Color::nearest(0xf0, 0x10, 0x08), Color::Composite(Red, Normal));
+    /// assert_eq!(Color::nearest(0x20, 0x20, 0x20), Color::Black);
+    /// ```
+    pub fn nearest(r: u8, g: u8, b: u8) -> Self {
+        let distance = |color: &Color| {
+            let (r2, g2, b2) = color.to_rgb();
+            let channel = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+
+            channel(r, r2) + channel(g, g2) + channel(b, b2)
+        };
+
+        Hue::ALL
+            .iter()
+            .flat_map(|&hue| {
+                Lightness::ALL
+                    .iter()
+                    .map(move |&lightness| Color::Composite(hue, lightness))
+            })
+            .chain(vec![Color::White, Color::Black])
+            .min_by_key(distance)
+            .unwrap() // The palette isn't empty.
+    }
+
+    /// Get the RGB triplet for a color. This is the inverse of `from_rgb`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use piet::{Color, Hue::*, Lightness::*};
+    ///
+    /// let color = Color::Composite(Cyan, Dark);
+    /// assert_eq!(color.to_rgb(), (0x00, 0xc0, 0xc0));
+    /// assert_eq!(Color::from_rgb(0x00, 0xc0, 0xc0), color);
+    /// ```
+    pub const fn to_rgb(self) -> (u8, u8, u8) {
+        use crate::{Color::*, Hue::*, Lightness::*};
+
+        let (high, low) = match self {
+            Composite(_, Light) => (0xff, 0xc0),
+            Composite(_, Normal) => (0xff, 0x00),
+            Composite(_, Dark) => (0xc0, 0x00),
+            White => return (0xff, 0xff, 0xff),
+            Black => return (0x00, 0x00, 0x00),
+        };
+
+        match self {
+            Composite(Red, _) => (high, low, low),
+            Composite(Yellow, _) => (high, high, low),
+            Composite(Green, _) => (low, high, low),
+            Composite(Cyan, _) => (low, high, high),
+            Composite(Blue, _) => (low, low, high),
+            Composite(Magenta, _) => (high, low, high),
+            _ => unreachable!(),
         }
     }
 
@@ -131,6 +209,52 @@ impl Color {
             _ => None,
         }
     }
+
+    /// Apply a transition to a color. This is the inverse of `transition`.
+    ///
+    /// # Returns
+    ///
+    /// The color that the transition leads to, or `None` for black and white.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use piet::{Color, Hue::*, Lightness::*};
+    ///
+    /// let a = Color::Composite(Blue, Light);
+    ///
+    /// assert_eq!(a.shifted((4, 2)), Some(Color::Composite(Green, Dark)));
+    /// ```
+    pub fn shifted(self, transition: (u8, u8)) -> Option<Color> {
+        let (hue_shift, lightness_shift) = transition;
+
+        match self {
+            Color::Composite(hue, lightness) => Some(Color::Composite(
+                Hue::ALL[(hue as usize + hue_shift as usize) % 6],
+                Lightness::ALL[(lightness as usize + lightness_shift as usize) % 3],
+            )),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Color {
+    /// Write a color as a name like "light red", "blue" or "white".
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Color::Composite(hue, lightness) => {
+                let hue = format!("{:?}", hue).to_lowercase();
+
+                match lightness {
+                    Lightness::Light => write!(f, "light {}", hue),
+                    Lightness::Normal => write!(f, "{}", hue),
+                    Lightness::Dark => write!(f, "dark {}", hue),
+                }
+            }
+            Color::Black => write!(f, "black"),
+            Color::White => write!(f, "white"),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -178,6 +302,34 @@ mod tests {
         assert_eq!(color, Color::Composite(Green, Normal));
     }
 
+    #[test]
+    fn test_color_to_rgb() {
+        use super::Hue::*;
+        use super::Lightness::*;
+
+        for &hue in &[Red, Yellow, Green, Cyan, Blue, Magenta] {
+            for &lightness in &[Light, Normal, Dark] {
+                let color = Color::Composite(hue, lightness);
+                let (r, g, b) = color.to_rgb();
+                assert_eq!(Color::from_rgb(r, g, b), color);
+            }
+        }
+
+        assert_eq!(Color::Black.to_rgb(), (0x00, 0x00, 0x00));
+        assert_eq!(Color::White.to_rgb(), (0xff, 0xff, 0xff));
+    }
+
+    #[test]
+    fn test_color_display() {
+        use super::Hue::*;
+        use super::Lightness::*;
+
+        assert_eq!(Color::Composite(Red, Light).to_string(), "light red");
+        assert_eq!(Color::Composite(Cyan, Normal).to_string(), "cyan");
+        assert_eq!(Color::Composite(Blue, Dark).to_string(), "dark blue");
+        assert_eq!(Color::White.to_string(), "white");
+    }
+
     #[test]
     fn test_color_transition() {
         use super::Hue::*;
@@ -1,7 +1,7 @@
 use self::Command::*;
 
 /// A command, which operates on Piet's stack.
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum Command {
     NoOp,
 
@@ -48,6 +48,44 @@ impl Command {
 
         Self::COMMAND_TABLE[hue as usize][lightness as usize]
     }
+
+    /// The change in hue and lightness that encodes this command. This is the
+    /// inverse of `from_transition`.
+    pub fn transition(self) -> (u8, u8) {
+        for (hue, row) in Self::COMMAND_TABLE.iter().enumerate() {
+            for (lightness, &command) in row.iter().enumerate() {
+                if command == self {
+                    return (hue as u8, lightness as u8);
+                }
+            }
+        }
+
+        unreachable!() // Every command is in the table.
+    }
+
+    /// A short, human readable name for the command.
+    pub const fn mnemonic(self) -> &'static str {
+        match self {
+            NoOp => "nop",
+            Push => "push",
+            Pop => "pop",
+            Duplicate => "dup",
+            Roll => "roll",
+            Add => "add",
+            Subtract => "sub",
+            Multiply => "mul",
+            Divide => "div",
+            Mod => "mod",
+            Not => "not",
+            Greater => "gt",
+            Pointer => "pointer",
+            Switch => "switch",
+            IntIn => "in num",
+            IntOut => "out num",
+            CharIn => "in char",
+            CharOut => "out char",
+        }
+    }
 }
 
 #[cfg(test)]
@@ -60,4 +98,14 @@ mod tests {
         assert_eq!(Command::from_transition((5, 2)), Command::CharOut);
         assert_eq!(Command::from_transition((3, 1)), Command::Pointer);
     }
+
+    #[test]
+    fn test_transition() {
+        assert_eq!(Command::NoOp.transition(), (0, 0));
+        assert_eq!(Command::CharOut.transition(), (5, 2));
+        assert_eq!(
+            Command::from_transition(Command::Roll.transition()),
+            Command::Roll
+        );
+    }
 }
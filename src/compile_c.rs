@@ -0,0 +1,494 @@
+use std::fmt::Write;
+
+use crate::cfg::Cfg;
+use crate::command::Command;
+use crate::errors::*;
+
+/// The stack, input and commands, which every compiled program starts with.
+///
+/// Each command behaves exactly like it does in `Interpreter`: arithmetic
+/// wraps, commands without enough values on the stack are ignored, and all of
+/// the input is read before the program starts.
+const RUNTIME: &str = r#"#include <inttypes.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+static int64_t *stack;
+static size_t len, capacity;
+
+static unsigned char *input;
+static size_t input_len, cursor;
+
+static void *allocate(void *pointer, size_t size) {
+    pointer = realloc(pointer, size);
+    if (!pointer) {
+        fputs("out of memory\n", stderr);
+        exit(1);
+    }
+    return pointer;
+}
+
+static void read_input(void) {
+    size_t input_capacity = 4096;
+    size_t read;
+
+    input = allocate(NULL, input_capacity);
+    while ((read = fread(input + input_len, 1, input_capacity - input_len, stdin)) > 0) {
+        input_len += read;
+        if (input_len == input_capacity) {
+            input_capacity *= 2;
+            input = allocate(input, input_capacity);
+        }
+    }
+}
+
+/* The character at `position`, and its length in bytes, or 0 at the end. */
+static size_t decode(size_t position, uint32_t *c) {
+    size_t length, i;
+
+    if (position >= input_len) {
+        return 0;
+    }
+    if (input[position] < 0x80) {
+        *c = input[position];
+        return 1;
+    }
+
+    length = input[position] >= 0xf0 ? 4 : input[position] >= 0xe0 ? 3 : 2;
+    *c = input[position] & (0x3f >> (length - 1));
+    for (i = 1; i < length && position + i < input_len; i++) {
+        *c = (*c << 6) | (input[position + i] & 0x3f);
+    }
+    return i;
+}
+
+/* The same characters as Rust's `char::is_whitespace`. */
+static int is_whitespace(uint32_t c) {
+    return (c >= 0x09 && c <= 0x0d) || c == 0x20 || c == 0x85 || c == 0xa0 || c == 0x1680 ||
+           (c >= 0x2000 && c <= 0x200a) || c == 0x2028 || c == 0x2029 || c == 0x202f ||
+           c == 0x205f || c == 0x3000;
+}
+
+static int64_t wrapping_add(int64_t a, int64_t b) { return (int64_t)((uint64_t)a + (uint64_t)b); }
+static int64_t wrapping_sub(int64_t a, int64_t b) { return (int64_t)((uint64_t)a - (uint64_t)b); }
+static int64_t wrapping_mul(int64_t a, int64_t b) { return (int64_t)((uint64_t)a * (uint64_t)b); }
+static int64_t wrapping_div(int64_t a, int64_t b) { return b == -1 ? wrapping_sub(0, a) : a / b; }
+static int64_t wrapping_rem(int64_t a, int64_t b) { return b == -1 ? 0 : a % b; }
+
+static void push(int64_t value) {
+    if (len == capacity) {
+        capacity = capacity ? capacity * 2 : 64;
+        stack = allocate(stack, capacity * sizeof *stack);
+    }
+    stack[len++] = value;
+}
+
+static inline void op_push(int64_t value) { push(value); }
+
+static inline void op_pop(void) {
+    if (len > 0) {
+        len--;
+    }
+}
+
+static inline void op_add(void) {
+    if (len >= 2) {
+        len--;
+        stack[len - 1] = wrapping_add(stack[len - 1], stack[len]);
+    }
+}
+
+static inline void op_subtract(void) {
+    if (len >= 2) {
+        len--;
+        stack[len - 1] = wrapping_sub(stack[len - 1], stack[len]);
+    }
+}
+
+static inline void op_multiply(void) {
+    if (len >= 2) {
+        len--;
+        stack[len - 1] = wrapping_mul(stack[len - 1], stack[len]);
+    }
+}
+
+static inline void op_divide(void) {
+    if (len >= 2 && stack[len - 1] != 0) {
+        len--;
+        stack[len - 1] = wrapping_div(stack[len - 1], stack[len]);
+    }
+}
+
+/* The result has the same sign as the divisor. */
+static inline void op_mod(void) {
+    if (len >= 2 && stack[len - 1] != 0) {
+        int64_t b = stack[len - 1];
+        len--;
+        int64_t r = wrapping_rem(stack[len - 1], b);
+        stack[len - 1] = r != 0 && (r < 0) != (b < 0) ? r + b : r;
+    }
+}
+
+static inline void op_not(void) {
+    if (len > 0) {
+        stack[len - 1] = stack[len - 1] == 0;
+    }
+}
+
+static inline void op_greater(void) {
+    if (len >= 2) {
+        len--;
+        stack[len - 1] = stack[len - 1] > stack[len];
+    }
+}
+
+/* How many times to rotate the DP clockwise. */
+static inline int op_pointer(void) {
+    if (len == 0) {
+        return 0;
+    }
+    len--;
+    return (int)((stack[len] % 4 + 4) % 4);
+}
+
+/* Whether to switch the CC. */
+static inline int op_switch(void) {
+    if (len == 0) {
+        return 0;
+    }
+    len--;
+    return (int)((stack[len] % 2 + 2) % 2);
+}
+
+static inline void op_duplicate(void) {
+    if (len > 0) {
+        push(stack[len - 1]);
+    }
+}
+
+static inline void op_roll(void) {
+    int64_t times, depth;
+    int64_t *range, *moved;
+
+    if (len < 2) {
+        return;
+    }
+
+    times = stack[len - 1];
+    depth = stack[len - 2];
+    if (depth == 0) {
+        len -= 2;
+        return;
+    }
+    if (depth < 0 || (uint64_t)depth > len - 2) {
+        return;
+    }
+
+    len -= 2;
+    times = (times % depth + depth) % depth;
+    if (times == 0) {
+        return;
+    }
+
+    range = stack + len - depth;
+    moved = allocate(NULL, (size_t)times * sizeof *moved);
+    memcpy(moved, range + depth - times, (size_t)times * sizeof *moved);
+    memmove(range + times, range, (size_t)(depth - times) * sizeof *range);
+    memcpy(range, moved, (size_t)times * sizeof *moved);
+    free(moved);
+}
+
+/* Read an integer, skipping any whitespace before it. If the input doesn't
+   start with an integer that fits, nothing is read. */
+static inline void op_in_num(void) {
+    size_t position = cursor, length, digits;
+    uint32_t c;
+    uint64_t magnitude = 0, limit = INT64_MAX;
+    int negative = 0;
+
+    while ((length = decode(position, &c)) > 0 && is_whitespace(c)) {
+        position += length;
+    }
+    if (position < input_len && (input[position] == '-' || input[position] == '+')) {
+        negative = input[position] == '-';
+        limit += negative;
+        position++;
+    }
+
+    for (digits = 0; position < input_len && input[position] >= '0' && input[position] <= '9'; digits++) {
+        uint64_t digit = input[position++] - '0';
+        if (magnitude > (limit - digit) / 10) {
+            return;
+        }
+        magnitude = magnitude * 10 + digit;
+    }
+    if (digits == 0) {
+        return;
+    }
+
+    cursor = position;
+    push(negative ? (int64_t)(0 - magnitude) : (int64_t)magnitude);
+}
+
+static inline void op_in_char(void) {
+    uint32_t c;
+    size_t length = decode(cursor, &c);
+
+    if (length > 0) {
+        cursor += length;
+        push(c);
+    }
+}
+
+static inline void op_out_num(void) {
+    if (len > 0) {
+        len--;
+        printf("%" PRId64, stack[len]);
+    }
+}
+
+/* Values that aren't characters are left on the stack. */
+static inline void op_out_char(void) {
+    int64_t value;
+    uint32_t c;
+
+    if (len == 0) {
+        return;
+    }
+
+    value = stack[len - 1];
+    c = (uint32_t)value;
+    if (value < 0 || c > 0x10ffff || (c >= 0xd800 && c <= 0xdfff)) {
+        return;
+    }
+
+    len--;
+    if (c < 0x80) {
+        putchar((int)c);
+    } else if (c < 0x800) {
+        putchar((int)(0xc0 | c >> 6));
+        putchar((int)(0x80 | (c & 0x3f)));
+    } else if (c < 0x10000) {
+        putchar((int)(0xe0 | c >> 12));
+        putchar((int)(0x80 | (c >> 6 & 0x3f)));
+        putchar((int)(0x80 | (c & 0x3f)));
+    } else {
+        putchar((int)(0xf0 | c >> 18));
+        putchar((int)(0x80 | (c >> 12 & 0x3f)));
+        putchar((int)(0x80 | (c >> 6 & 0x3f)));
+        putchar((int)(0x80 | (c & 0x3f)));
+    }
+}
+"#;
+
+/// Compile a program to a standalone C file, which reads its input from stdin
+/// and writes its output to stdout.
+///
+/// Every state in the control flow graph becomes a label in `main`, followed by
+/// the command of the move out of it and a `goto` to the next state. `Pointer`
+/// and `Switch` become a `switch` over the outcomes.
+///
+/// # Errors
+///
+/// `ErrorKind::UnsupportedDialect` unless the dialect of the graph
+/// [executes commands like npiet](crate::Dialect::executes_like_npiet).
+pub fn compile_c(cfg: &Cfg) -> Result<String> {
+    if !cfg.dialect().executes_like_npiet() {
+        return Err(ErrorKind::UnsupportedDialect.into());
+    }
+
+    let states = cfg.states();
+
+    // Only states that are jumped to need a label, since unused labels are a
+    // warning. Everything else falls through from the state before it.
+    let mut jumped_to = vec![false; states.len()];
+    for (index, &state) in states.iter().enumerate() {
+        let edges = cfg.edges(state);
+
+        for edge in edges {
+            let to = cfg.index(edge.to).unwrap(); // Every target is reachable.
+            jumped_to[to] |= edges.len() > 1 || to != index + 1;
+        }
+    }
+
+    let mut source = String::from(RUNTIME);
+
+    writeln!(source).unwrap();
+    writeln!(source, "int main(void) {{").unwrap();
+    writeln!(source, "    read_input();").unwrap();
+
+    for (index, &state) in states.iter().enumerate() {
+        let (id, dp, cc) = state;
+        let block = &cfg.blocks()[id];
+        let (x, y) = block.origin();
+
+        writeln!(source).unwrap();
+        if jumped_to[index] {
+            write!(source, "s{}: ", index).unwrap();
+        } else {
+            write!(source, "    ").unwrap();
+        }
+        writeln!(
+            source,
+            "/* block {} at ({}, {}), {}, {} {} */",
+            id,
+            x,
+            y,
+            block.color(),
+            dp,
+            cc
+        )
+        .unwrap();
+
+        let edges = cfg.edges(state);
+        let edge = match edges.first() {
+            Some(edge) => edge,
+            None => {
+                writeln!(source, "    return 0;").unwrap();
+                continue;
+            }
+        };
+
+        if edges.len() > 1 {
+            let command = edge.command.unwrap(); // Only commands can branch.
+            writeln!(source, "    switch ({}()) {{", function(command)).unwrap();
+
+            for edge in edges {
+                writeln!(
+                    source,
+                    "    case {}: goto s{};",
+                    edge.branch.unwrap_or(0),
+                    cfg.index(edge.to).unwrap()
+                )
+                .unwrap();
+            }
+
+            writeln!(source, "    }}").unwrap();
+            continue;
+        }
+
+        match edge.command {
+            Some(Command::NoOp) | None => {}
+            Some(Command::Push) => {
+                writeln!(source, "    op_push({});", block.size()).unwrap();
+            }
+            Some(command) => {
+                writeln!(source, "    {}();", function(command)).unwrap();
+            }
+        }
+
+        let to = cfg.index(edge.to).unwrap();
+        if to != index + 1 {
+            writeln!(source, "    goto s{};", to).unwrap();
+        }
+    }
+
+    if states.is_empty() {
+        writeln!(source, "    return 0;").unwrap();
+    }
+
+    writeln!(source, "}}").unwrap();
+
+    Ok(source)
+}
+
+/// The runtime function that executes a command.
+fn function(command: Command) -> &'static str {
+    match command {
+        Command::NoOp => "",
+        Command::Push => "op_push",
+        Command::Pop => "op_pop",
+        Command::Add => "op_add",
+        Command::Subtract => "op_subtract",
+        Command::Multiply => "op_multiply",
+        Command::Divide => "op_divide",
+        Command::Mod => "op_mod",
+        Command::Not => "op_not",
+        Command::Greater => "op_greater",
+        Command::Pointer => "op_pointer",
+        Command::Switch => "op_switch",
+        Command::Duplicate => "op_duplicate",
+        Command::Roll => "op_roll",
+        Command::IntIn => "op_in_num",
+        Command::CharIn => "op_in_char",
+        Command::IntOut => "op_out_num",
+        Command::CharOut => "op_out_char",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Write as _;
+    use std::process::{self, Stdio};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::differential::{self, INPUT};
+    use crate::program::Program;
+
+    /// Compile a program to C, build it with the system's C compiler, and run
+    /// it with `input`.
+    fn run_c(program: &Program, input: &str) -> String {
+        let dir = differential::scratch_dir("c");
+        let (source, binary) = (dir.join("program.c"), dir.join("program"));
+        fs::write(&source, compile_c(&Cfg::new(program)).unwrap()).unwrap();
+
+        let output = process::Command::new("cc")
+            .args(["-std=c99", "-Wall", "-Werror", "-O1", "-o"])
+            .arg(&binary)
+            .arg(&source)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+
+        let mut child = process::Command::new(&binary)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(input.as_bytes())
+            .unwrap();
+
+        let start = Instant::now();
+        while child.try_wait().unwrap().is_none() {
+            if start.elapsed() > Duration::from_secs(10) {
+                child.kill().unwrap();
+                panic!("the compiled program didn't halt");
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        let output = child.wait_with_output().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    #[test]
+    #[ignore = "needs a C compiler called `cc`; run with `cargo test -- --ignored`"]
+    fn test_compile_c() {
+        for program in &differential::command_programs() {
+            for input in &[INPUT, ""] {
+                let expected = differential::interpret(program, input).unwrap();
+                assert_eq!(run_c(program, input), expected);
+            }
+        }
+
+        for program in &differential::random_programs(40) {
+            let expected = differential::interpret(program, INPUT).unwrap();
+            assert_eq!(run_c(program, INPUT), expected);
+        }
+    }
+}
@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::cfg::Cfg;
+use crate::command::Command;
+use crate::errors::*;
+
+/// Compile a program to Rust source code, as a function
+/// `pub fn run(input: &str) -> String` that returns everything the program
+/// writes.
+///
+/// The code depends on the `piet` crate: every command is executed by a
+/// `Machine`, so it behaves exactly like it does in `Interpreter`. Control
+/// flow is compiled away. Each straight line of moves becomes an arm of a
+/// `match` in a loop, which ends by choosing the next arm, or `None` to halt.
+///
+/// # Errors
+///
+/// `ErrorKind::UnsupportedDialect` unless the dialect of the graph
+/// [executes commands like npiet](crate::Dialect::executes_like_npiet).
+pub fn compile_rust(cfg: &Cfg) -> Result<String> {
+    if !cfg.dialect().executes_like_npiet() {
+        return Err(ErrorKind::UnsupportedDialect.into());
+    }
+
+    let states = cfg.states();
+    let leaders = cfg.leaders();
+    let arms = leaders
+        .iter()
+        .enumerate()
+        .map(|(arm, &index)| (states[index], arm))
+        .collect::<HashMap<_, _>>();
+
+    let mut source = String::new();
+
+    writeln!(
+        source,
+        "/// Run the program with `input`, and return everything it writes."
+    )
+    .unwrap();
+    writeln!(source, "pub fn run(input: &str) -> String {{").unwrap();
+    writeln!(source, "    let mut machine = piet::Machine::new();").unwrap();
+    writeln!(source, "    machine.feed_input(input);").unwrap();
+
+    if !states.is_empty() {
+        writeln!(source).unwrap();
+        writeln!(source, "    let mut arm = Some(0);").unwrap();
+        writeln!(source, "    while let Some(current) = arm {{").unwrap();
+        writeln!(source, "        arm = match current {{").unwrap();
+    }
+
+    for (arm, &leader) in leaders.iter().enumerate() {
+        let (id, dp, cc) = states[leader];
+        let block = &cfg.blocks()[id];
+        let (x, y) = block.origin();
+
+        writeln!(
+            source,
+            "            // Block {} at ({}, {}), {}, {} {}.",
+            id,
+            x,
+            y,
+            block.color(),
+            dp,
+            cc
+        )
+        .unwrap();
+        writeln!(source, "            {} => {{", arm).unwrap();
+
+        let mut state = states[leader];
+
+        loop {
+            let size = cfg.blocks()[state.0].size();
+            let edges = cfg.edges(state);
+            let edge = match edges.first() {
+                Some(edge) => edge,
+                None => {
+                    writeln!(source, "                None").unwrap();
+                    break;
+                }
+            };
+
+            if edges.len() > 1 {
+                let command = edge.command.unwrap(); // Only commands can branch.
+                writeln!(
+                    source,
+                    "                match machine.execute(piet::Command::{:?}, {}) {{",
+                    command, size
+                )
+                .unwrap();
+
+                for (number, edge) in edges.iter().enumerate() {
+                    let pattern = if number + 1 == edges.len() {
+                        "_".to_string()
+                    } else {
+                        edge.branch.unwrap_or(0).to_string()
+                    };
+
+                    writeln!(
+                        source,
+                        "                    {} => Some({}),",
+                        pattern, arms[&edge.to]
+                    )
+                    .unwrap();
+                }
+
+                writeln!(source, "                }}").unwrap();
+                break;
+            }
+
+            match edge.command {
+                Some(Command::NoOp) | None => {}
+                Some(command) => {
+                    writeln!(
+                        source,
+                        "                machine.execute(piet::Command::{:?}, {});",
+                        command, size
+                    )
+                    .unwrap();
+                }
+            }
+
+            match arms.get(&edge.to) {
+                Some(arm) => {
+                    writeln!(source, "                Some({})", arm).unwrap();
+                    break;
+                }
+                None => state = edge.to,
+            }
+        }
+
+        writeln!(source, "            }}").unwrap();
+    }
+
+    if !states.is_empty() {
+        writeln!(source, "            _ => unreachable!(),").unwrap();
+        writeln!(source, "        }};").unwrap();
+        writeln!(source, "    }}").unwrap();
+        writeln!(source).unwrap();
+    }
+
+    writeln!(source, "    machine.output().to_string()").unwrap();
+    writeln!(source, "}}").unwrap();
+
+    Ok(source)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Write as _;
+    use std::path::PathBuf;
+    use std::process::{self, Stdio};
+
+    use super::*;
+    use crate::differential::{self, INPUT};
+    use crate::program::Program;
+
+    /// Build the `piet` library with Cargo, and find the compiled library in
+    /// the artifacts it reports.
+    fn library() -> PathBuf {
+        let manifest = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml");
+        let output = process::Command::new(env!("CARGO"))
+            .args(["build", "--lib", "--message-format=json", "--manifest-path"])
+            .arg(manifest)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+
+        // Each artifact is reported on its own line, as JSON like
+        // `{"reason":"compiler-artifact",..."filenames":["/.../libpiet.rlib",...],...}`.
+        let messages = String::from_utf8(output.stdout).unwrap();
+        let library = messages
+            .lines()
+            .filter(|line| line.contains("\"reason\":\"compiler-artifact\""))
+            .flat_map(|line| line.split('"'))
+            .find(|part| {
+                let name = part.rsplit(['/', '\\']).next().unwrap();
+                name.starts_with("libpiet") && name.ends_with(".rlib")
+            })
+            .expect("cargo didn't report building the piet library");
+
+        PathBuf::from(library)
+    }
+
+    /// Compile programs to Rust, and build them into one executable that runs
+    /// the program chosen by its first argument.
+    fn build(programs: &[Program]) -> PathBuf {
+        let library = library();
+
+        let mut source = String::new();
+        let mut calls = String::new();
+        for (index, program) in programs.iter().enumerate() {
+            writeln!(source, "mod p{} {{", index).unwrap();
+            source.push_str(&compile_rust(&Cfg::new(program)).unwrap());
+            writeln!(source, "}}").unwrap();
+            writeln!(calls, "        {} => p{}::run(&input),", index, index).unwrap();
+        }
+        write!(
+            source,
+            "
+fn main() {{
+    use std::io::Read;
+
+    let mut input = String::new();
+    std::io::stdin().read_to_string(&mut input).unwrap();
+
+    let output = match std::env::args().nth(1).unwrap().parse::<usize>().unwrap() {{
+{}        _ => unreachable!(),
+    }};
+    print!(\"{{}}\", output);
+}}
+",
+            calls
+        )
+        .unwrap();
+
+        let dir = differential::scratch_dir("rust");
+        let (path, binary) = (dir.join("main.rs"), dir.join("main"));
+        fs::write(&path, source).unwrap();
+
+        let output = process::Command::new("rustc")
+            .args(["--edition", "2018", "-D", "warnings", "-L"])
+            .arg(library.parent().unwrap().join("deps"))
+            .arg("--extern")
+            .arg(format!("piet={}", library.display()))
+            .arg("-o")
+            .arg(&binary)
+            .arg(&path)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+
+        binary
+    }
+
+    fn run(binary: &PathBuf, index: usize, input: &str) -> String {
+        let mut child = process::Command::new(binary)
+            .arg(index.to_string())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(input.as_bytes())
+            .unwrap();
+
+        String::from_utf8(child.wait_with_output().unwrap().stdout).unwrap()
+    }
+
+    #[test]
+    fn test_compile_rust() {
+        let mut programs = differential::command_programs();
+        let commands = programs.len();
+        programs.extend(differential::random_programs(40));
+
+        let binary = build(&programs);
+
+        for (index, program) in programs.iter().enumerate() {
+            let inputs: &[&str] = if index < commands {
+                &[INPUT, ""]
+            } else {
+                &[INPUT]
+            };
+
+            for input in inputs {
+                let expected = differential::interpret(program, input).unwrap();
+                assert_eq!(run(&binary, index, input), expected);
+            }
+        }
+
+        fs::remove_dir_all(binary.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_compile_rust_listing() {
+        use crate::color::{Color, Hue::*, Lightness::*};
+
+        const B: Color = Color::Black;
+        const LR: Color = Color::Composite(Red, Light);
+        const NR: Color = Color::Composite(Red, Normal);
+        const DR: Color = Color::Composite(Red, Dark);
+        const LY: Color = Color::Composite(Yellow, Light);
+
+        let program = Program::new((9, 2), vec![
+            LR, LR, LR, NR, NR, DR, LY, NR, B,
+            B,  B,  B,  B,  B,  B,  NR, NR, B,
+        ]);
+        let source = compile_rust(&Cfg::new(&program)).unwrap();
+
+        assert!(source.contains("
+            0 => {
+                machine.execute(piet::Command::Push, 3);
+                machine.execute(piet::Command::Push, 2);
+                machine.execute(piet::Command::Subtract, 1);
+                machine.execute(piet::Command::IntOut, 1);
+                None
+            }
+"));
+    }
+}
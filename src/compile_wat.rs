@@ -0,0 +1,479 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::cfg::Cfg;
+use crate::command::Command;
+use crate::errors::*;
+
+/// The imports, stack and commands, which every compiled module starts with.
+///
+/// The stack lives at the start of the exported memory, one little-endian
+/// `i64` per value with the top last, and the memory grows as needed. Each
+/// command behaves exactly like it does in `Interpreter`.
+const RUNTIME: &str = r#"  ;; Read a number the way `Machine::read_int` does. The first result is 1 if
+  ;; a number was read, and 0 if the input doesn't start with one.
+  (import "io" "in_num" (func $in_num (result i32 i64)))
+  ;; Read a character, returning -1 at the end of the input.
+  (import "io" "in_char" (func $in_char (result i32)))
+  (import "io" "out_num" (func $out_num (param i64)))
+  ;; Write a character. It's always a valid Unicode scalar value.
+  (import "io" "out_char" (func $out_char (param i32)))
+
+  (memory (export "memory") 1)
+
+  ;; The number of values on the stack.
+  (global $len (export "len") (mut i32) (i32.const 0))
+
+  ;; The address of the value `depth` places below the top.
+  (func $at (param $depth i32) (result i32)
+    (i32.mul
+      (i32.sub (i32.sub (global.get $len) (i32.const 1)) (local.get $depth))
+      (i32.const 8)))
+
+  (func $push (param $value i64)
+    (if (i32.gt_u
+          (i32.mul (i32.add (global.get $len) (i32.const 1)) (i32.const 8))
+          (i32.mul (memory.size) (i32.const 65536)))
+      (then
+        (if (i32.eq (memory.grow (i32.const 1)) (i32.const -1))
+          (then unreachable))))
+    (i64.store (i32.mul (global.get $len) (i32.const 8)) (local.get $value))
+    (global.set $len (i32.add (global.get $len) (i32.const 1))))
+
+  ;; Remove the top value, and return it.
+  (func $pop (result i64)
+    (global.set $len (i32.sub (global.get $len) (i32.const 1)))
+    (i64.load (i32.mul (global.get $len) (i32.const 8))))
+
+  ;; Replace the top value.
+  (func $set_top (param $value i64)
+    (i64.store (call $at (i32.const 0)) (local.get $value)))
+
+  (func $top (result i64)
+    (i64.load (call $at (i32.const 0))))
+
+  (func $op_push (param $value i64)
+    (call $push (local.get $value)))
+
+  (func $op_pop
+    (if (global.get $len)
+      (then (drop (call $pop)))))
+
+  (func $op_add (local $b i64)
+    (if (i32.ge_u (global.get $len) (i32.const 2))
+      (then
+        (local.set $b (call $pop))
+        (call $set_top (i64.add (call $top) (local.get $b))))))
+
+  (func $op_subtract (local $b i64)
+    (if (i32.ge_u (global.get $len) (i32.const 2))
+      (then
+        (local.set $b (call $pop))
+        (call $set_top (i64.sub (call $top) (local.get $b))))))
+
+  (func $op_multiply (local $b i64)
+    (if (i32.ge_u (global.get $len) (i32.const 2))
+      (then
+        (local.set $b (call $pop))
+        (call $set_top (i64.mul (call $top) (local.get $b))))))
+
+  ;; Dividing the smallest number by -1 wraps around, instead of trapping.
+  (func $op_divide (local $b i64)
+    (if (i32.ge_u (global.get $len) (i32.const 2))
+      (then
+        (if (i64.ne (call $top) (i64.const 0))
+          (then
+            (local.set $b (call $pop))
+            (if (i64.eq (local.get $b) (i64.const -1))
+              (then (call $set_top (i64.sub (i64.const 0) (call $top))))
+              (else (call $set_top (i64.div_s (call $top) (local.get $b))))))))))
+
+  ;; The result has the same sign as the divisor.
+  (func $op_mod (local $b i64) (local $r i64)
+    (if (i32.ge_u (global.get $len) (i32.const 2))
+      (then
+        (if (i64.ne (call $top) (i64.const 0))
+          (then
+            (local.set $b (call $pop))
+            (local.set $r (i64.rem_s (call $top) (local.get $b)))
+            (if (i32.and
+                  (i64.ne (local.get $r) (i64.const 0))
+                  (i32.ne
+                    (i64.lt_s (local.get $r) (i64.const 0))
+                    (i64.lt_s (local.get $b) (i64.const 0))))
+              (then (local.set $r (i64.add (local.get $r) (local.get $b)))))
+            (call $set_top (local.get $r)))))))
+
+  (func $op_not
+    (if (global.get $len)
+      (then (call $set_top (i64.extend_i32_u (i64.eqz (call $top)))))))
+
+  (func $op_greater (local $b i64)
+    (if (i32.ge_u (global.get $len) (i32.const 2))
+      (then
+        (local.set $b (call $pop))
+        (call $set_top (i64.extend_i32_u (i64.gt_s (call $top) (local.get $b)))))))
+
+  ;; How many times to rotate the DP clockwise.
+  (func $op_pointer (result i32)
+    (if (result i32) (global.get $len)
+      (then
+        (i32.wrap_i64
+          (i64.rem_s (i64.add (i64.rem_s (call $pop) (i64.const 4)) (i64.const 4)) (i64.const 4))))
+      (else (i32.const 0))))
+
+  ;; Whether to switch the CC.
+  (func $op_switch (result i32)
+    (if (result i32) (global.get $len)
+      (then
+        (i32.wrap_i64
+          (i64.rem_s (i64.add (i64.rem_s (call $pop) (i64.const 2)) (i64.const 2)) (i64.const 2))))
+      (else (i32.const 0))))
+
+  (func $op_duplicate
+    (if (global.get $len)
+      (then (call $push (call $top)))))
+
+  ;; Reverse the values from address `$from` up to, but not including, `$to`.
+  (func $reverse (param $from i32) (param $to i32) (local $value i64)
+    (block $done
+      (loop $swap
+        (br_if $done (i32.ge_u (local.get $from) (local.get $to)))
+        (local.set $to (i32.sub (local.get $to) (i32.const 8)))
+        (br_if $done (i32.ge_u (local.get $from) (local.get $to)))
+        (local.set $value (i64.load (local.get $from)))
+        (i64.store (local.get $from) (i64.load (local.get $to)))
+        (i64.store (local.get $to) (local.get $value))
+        (local.set $from (i32.add (local.get $from) (i32.const 8)))
+        (br $swap))))
+
+  (func $op_roll (local $times i64) (local $depth i64) (local $start i32) (local $split i32)
+    (if (i32.lt_u (global.get $len) (i32.const 2))
+      (then (return)))
+
+    (local.set $times (call $top))
+    (local.set $depth (i64.load (call $at (i32.const 1))))
+    (if (i64.eqz (local.get $depth))
+      (then
+        (global.set $len (i32.sub (global.get $len) (i32.const 2)))
+        (return)))
+    (if (i32.or
+          (i64.lt_s (local.get $depth) (i64.const 0))
+          (i64.gt_u (local.get $depth) (i64.extend_i32_u (i32.sub (global.get $len) (i32.const 2)))))
+      (then (return)))
+
+    (global.set $len (i32.sub (global.get $len) (i32.const 2)))
+    (local.set $times
+      (i64.rem_s
+        (i64.add (i64.rem_s (local.get $times) (local.get $depth)) (local.get $depth))
+        (local.get $depth)))
+
+    ;; Rotating the top `$depth` values `$times` places is the same as
+    ;; reversing them all, then reversing both parts.
+    (local.set $start
+      (i32.mul (i32.sub (global.get $len) (i32.wrap_i64 (local.get $depth))) (i32.const 8)))
+    (local.set $split
+      (i32.add (local.get $start) (i32.mul (i32.wrap_i64 (local.get $times)) (i32.const 8))))
+    (call $reverse (local.get $start) (i32.mul (global.get $len) (i32.const 8)))
+    (call $reverse (local.get $start) (local.get $split))
+    (call $reverse (local.get $split) (i32.mul (global.get $len) (i32.const 8))))
+
+  (func $op_in_num (local $value i64)
+    (call $in_num)
+    (local.set $value)
+    (if
+      (then (call $push (local.get $value)))))
+
+  (func $op_in_char (local $c i32)
+    (local.set $c (call $in_char))
+    (if (i32.ge_s (local.get $c) (i32.const 0))
+      (then (call $push (i64.extend_i32_u (local.get $c))))))
+
+  (func $op_out_num
+    (if (global.get $len)
+      (then (call $out_num (call $pop)))))
+
+  ;; Values that aren't characters are left on the stack.
+  (func $op_out_char (local $c i32)
+    (if (i32.eqz (global.get $len))
+      (then (return)))
+    (if (i64.lt_s (call $top) (i64.const 0))
+      (then (return)))
+
+    (local.set $c (i32.wrap_i64 (call $top)))
+    (if (i32.or
+          (i32.gt_u (local.get $c) (i32.const 0x10ffff))
+          (i32.and
+            (i32.ge_u (local.get $c) (i32.const 0xd800))
+            (i32.le_u (local.get $c) (i32.const 0xdfff))))
+      (then (return)))
+
+    (drop (call $pop))
+    (call $out_char (local.get $c)))
+"#;
+
+/// Compile a program to a WebAssembly module, in the text format.
+///
+/// The module exports a `run` function, which runs the program to the end,
+/// and its `memory`. Input and output go through functions that it imports
+/// from `io`: `in_num`, `in_char`, `out_num` and `out_char`.
+///
+/// Each straight line of moves becomes a block in a loop, which ends by
+/// storing the number of the next block, or halting.
+///
+/// # Errors
+///
+/// `ErrorKind::UnsupportedDialect` unless the dialect of the graph
+/// [executes commands like npiet](crate::Dialect::executes_like_npiet).
+pub fn compile_wat(cfg: &Cfg) -> Result<String> {
+    if !cfg.dialect().executes_like_npiet() {
+        return Err(ErrorKind::UnsupportedDialect.into());
+    }
+
+    let states = cfg.states();
+    let leaders = cfg.leaders();
+    let arms = leaders
+        .iter()
+        .enumerate()
+        .map(|(arm, &index)| (states[index], arm))
+        .collect::<HashMap<_, _>>();
+
+    let mut source = String::from("(module\n");
+    source.push_str(RUNTIME);
+
+    writeln!(source).unwrap();
+    writeln!(source, "  (func (export \"run\") (local $arm i32)").unwrap();
+    writeln!(source, "    loop $dispatch").unwrap();
+    writeln!(source, "      block $halt").unwrap();
+
+    // Every arm is the code after the end of a block, so the first arm is the
+    // innermost block.
+    for arm in (0..leaders.len()).rev() {
+        writeln!(source, "      block $arm{}", arm).unwrap();
+    }
+
+    let targets = (0..leaders.len())
+        .map(|arm| format!("$arm{}", arm))
+        .collect::<Vec<_>>();
+    writeln!(source, "      local.get $arm").unwrap();
+    writeln!(source, "      br_table {} $halt", targets.join(" ")).unwrap();
+
+    for &leader in &leaders {
+        let (id, dp, cc) = states[leader];
+        let block = &cfg.blocks()[id];
+        let (x, y) = block.origin();
+
+        writeln!(source, "      end").unwrap();
+        writeln!(
+            source,
+            "      ;; block {} at ({}, {}), {}, {} {}",
+            id,
+            x,
+            y,
+            block.color(),
+            dp,
+            cc
+        )
+        .unwrap();
+
+        let mut state = states[leader];
+
+        loop {
+            let size = cfg.blocks()[state.0].size();
+            let edges = cfg.edges(state);
+            let edge = match edges.first() {
+                Some(edge) => edge,
+                None => {
+                    writeln!(source, "      br $halt").unwrap();
+                    break;
+                }
+            };
+
+            if edges.len() > 1 {
+                // Another table, with a block for every outcome.
+                for _ in edges {
+                    writeln!(source, "      block").unwrap();
+                }
+
+                let command = edge.command.unwrap(); // Only commands can branch.
+                let depths = (0..edges.len())
+                    .map(|depth| depth.to_string())
+                    .collect::<Vec<_>>();
+                writeln!(source, "      call {}", function(command)).unwrap();
+                writeln!(source, "      br_table {}", depths.join(" ")).unwrap();
+
+                for edge in edges {
+                    writeln!(source, "      end").unwrap();
+                    writeln!(
+                        source,
+                        "      (local.set $arm (i32.const {}))",
+                        arms[&edge.to]
+                    )
+                    .unwrap();
+                    writeln!(source, "      br $dispatch").unwrap();
+                }
+                break;
+            }
+
+            match edge.command {
+                Some(Command::NoOp) | None => {}
+                Some(Command::Push) => {
+                    writeln!(source, "      (call $op_push (i64.const {}))", size).unwrap();
+                }
+                Some(command) => {
+                    writeln!(source, "      call {}", function(command)).unwrap();
+                }
+            }
+
+            match arms.get(&edge.to) {
+                Some(arm) => {
+                    writeln!(source, "      (local.set $arm (i32.const {}))", arm).unwrap();
+                    writeln!(source, "      br $dispatch").unwrap();
+                    break;
+                }
+                None => state = edge.to,
+            }
+        }
+    }
+
+    writeln!(source, "      end").unwrap();
+    writeln!(source, "    end").unwrap();
+    writeln!(source, "  )").unwrap();
+    writeln!(source, ")").unwrap();
+
+    Ok(source)
+}
+
+/// The runtime function that executes a command.
+fn function(command: Command) -> &'static str {
+    match command {
+        Command::NoOp => "",
+        Command::Push => "$op_push",
+        Command::Pop => "$op_pop",
+        Command::Add => "$op_add",
+        Command::Subtract => "$op_subtract",
+        Command::Multiply => "$op_multiply",
+        Command::Divide => "$op_divide",
+        Command::Mod => "$op_mod",
+        Command::Not => "$op_not",
+        Command::Greater => "$op_greater",
+        Command::Pointer => "$op_pointer",
+        Command::Switch => "$op_switch",
+        Command::Duplicate => "$op_duplicate",
+        Command::Roll => "$op_roll",
+        Command::IntIn => "$op_in_num",
+        Command::CharIn => "$op_in_char",
+        Command::IntOut => "$op_out_num",
+        Command::CharOut => "$op_out_char",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wasmi::{Caller, Engine, Linker, Module, Store};
+
+    use super::*;
+    use crate::differential::{self, INPUT};
+    use crate::machine::Machine;
+    use crate::program::Program;
+
+    /// What the compiled module imports: input is read by a `Machine`, so it's
+    /// parsed the same way as in the interpreter.
+    struct Host {
+        machine: Machine,
+        output: String,
+    }
+
+    fn run_wat(program: &Program, input: &str) -> String {
+        let wasm = wat::parse_str(compile_wat(&Cfg::new(program)).unwrap()).unwrap();
+        let engine = Engine::default();
+        let module = Module::new(&engine, &wasm).unwrap();
+
+        let mut machine = Machine::new();
+        machine.feed_input(input);
+        let host = Host {
+            machine,
+            output: String::new(),
+        };
+        let mut store = Store::new(&engine, host);
+
+        let mut linker = Linker::<Host>::new(&engine);
+        linker
+            .func_wrap("io", "in_num", |mut caller: Caller<Host>| {
+                match caller.data_mut().machine.read_int() {
+                    Some(value) => (1, value),
+                    None => (0, 0),
+                }
+            })
+            .unwrap()
+            .func_wrap("io", "in_char", |mut caller: Caller<Host>| {
+                caller
+                    .data_mut()
+                    .machine
+                    .read_char()
+                    .map_or(-1, |c| c as i32)
+            })
+            .unwrap()
+            .func_wrap("io", "out_num", |mut caller: Caller<Host>, value: i64| {
+                caller.data_mut().output.push_str(&value.to_string());
+            })
+            .unwrap()
+            .func_wrap("io", "out_char", |mut caller: Caller<Host>, c: i32| {
+                let c = std::char::from_u32(c as u32).unwrap();
+                caller.data_mut().output.push(c);
+            })
+            .unwrap();
+
+        let instance = linker
+            .instantiate(&mut store, &module)
+            .unwrap()
+            .start(&mut store)
+            .unwrap();
+        instance
+            .get_typed_func::<(), ()>(&store, "run")
+            .unwrap()
+            .call(&mut store, ())
+            .unwrap();
+
+        store.into_data().output
+    }
+
+    #[test]
+    fn test_compile_wat() {
+        for program in &differential::command_programs() {
+            for input in &[INPUT, ""] {
+                let expected = differential::interpret(program, input).unwrap();
+                assert_eq!(run_wat(program, input), expected);
+            }
+        }
+
+        for program in &differential::random_programs(40) {
+            let expected = differential::interpret(program, INPUT).unwrap();
+            assert_eq!(run_wat(program, INPUT), expected);
+        }
+    }
+
+    #[test]
+    fn test_compile_wat_empty() {
+        let program = Program::new((0, 0), vec![]);
+        assert_eq!(run_wat(&program, ""), "");
+    }
+
+    #[test]
+    fn test_compile_wat_dialect() {
+        use crate::dialect::{Dialect, StartOnWhite};
+
+        let program = Program::new((0, 0), vec![]);
+        let moves = Dialect {
+            white_retry_limit: Some(0),
+            start_on_white: StartOnWhite::Halt,
+            ..Dialect::NPIET
+        };
+        assert!(compile_wat(&Cfg::with_dialect(&program, moves)).is_ok());
+
+        match compile_wat(&Cfg::with_dialect(&program, Dialect::STRICT)) {
+            Err(Error(ErrorKind::UnsupportedDialect, _)) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+}
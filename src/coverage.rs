@@ -0,0 +1,531 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+use image::{Rgb, RgbImage};
+
+use crate::blocks::{BlockId, Blocks};
+use crate::color::Color;
+use crate::command::Command;
+use crate::errors::*;
+use crate::interpreter::{CodelChooser, DirectionPointer};
+use crate::program::Program;
+use crate::render::Canvas;
+use crate::trace::{Step, Trace};
+
+const UNCOVERED_COLOR: Rgb<u8> = Rgb([0xff, 0x00, 0x80]);
+
+/// A way to leave a color block: the block, and the direction of the DP and
+/// CC.
+pub type Exit = (BlockId, DirectionPointer, CodelChooser);
+
+/// Which parts of a program were executed, over one or more runs.
+///
+/// Three things are tracked:
+///
+/// - Which colored blocks were entered.
+/// - Which exits were taken, out of all the exits that aren't blocked.
+/// - For exits that execute `Pointer` or `Switch`, which of the possible
+///   directions the DP or CC was turned in.
+#[derive(Debug, Clone)]
+pub struct Coverage {
+    blocks: Blocks,
+    fingerprint: String,
+    possible_exits: HashSet<Exit>,
+    possible_branches: HashMap<Exit, Command>,
+    entered: Vec<bool>,
+    exits: HashSet<Exit>,
+    branches: HashMap<Exit, u8>,
+}
+
+impl Coverage {
+    pub fn new(program: &Program) -> Self {
+        let blocks = Blocks::new(program);
+        let possible_exits = open_exits(program, &blocks);
+        let possible_branches = branch_exits(program, &blocks, &possible_exits);
+
+        Coverage {
+            entered: vec![false; blocks.len()],
+            blocks,
+            fingerprint: fingerprint(program),
+            possible_exits,
+            possible_branches,
+            exits: HashSet::new(),
+            branches: HashMap::new(),
+        }
+    }
+
+    /// Add a run of the program to the coverage.
+    pub fn record_trace(&mut self, trace: &Trace) {
+        // The interpreter always starts in the first block.
+        if let Some(entered) = self.entered.first_mut() {
+            *entered = true;
+        }
+
+        for step in trace.steps() {
+            self.record(step);
+        }
+    }
+
+    /// Add a single step to the coverage.
+    pub fn record(&mut self, step: &Step) {
+        if let Some(to) = self.blocks.id_at(step.to) {
+            self.entered[to] = true;
+        }
+
+        let from = match self.blocks.id_at(step.from) {
+            Some(from) if is_code(self.blocks[from].color()) => from,
+            _ => return,
+        };
+
+        let exit = (from, step.dp, step.cc);
+        self.exits.insert(exit);
+
+        let outcome = match step.command {
+            Some(Command::Pointer) => {
+                (direction_index(step.next_dp) + 4 - direction_index(step.dp)) % 4
+            }
+            Some(Command::Switch) => (step.next_cc != step.cc) as u8,
+            _ => return,
+        };
+
+        *self.branches.entry(exit).or_insert(0) |= 1 << outcome;
+    }
+
+    /// Combine the coverage of another set of runs of the same program into
+    /// this one.
+    pub fn merge(&mut self, other: &Coverage) {
+        for (entered, &other) in self.entered.iter_mut().zip(&other.entered) {
+            *entered |= other;
+        }
+
+        self.exits.extend(other.exits.iter().copied());
+
+        for (&exit, &outcomes) in &other.branches {
+            *self.branches.entry(exit).or_insert(0) |= outcomes;
+        }
+    }
+
+    pub fn block_entered(&self, block: BlockId) -> bool {
+        self.entered.get(block).copied().unwrap_or(false)
+    }
+
+    pub fn exit_taken(&self, exit: Exit) -> bool {
+        self.exits.contains(&exit)
+    }
+
+    /// How many colored blocks were entered, out of how many there are.
+    pub fn blocks_covered(&self) -> (usize, usize) {
+        let code = self
+            .blocks
+            .iter()
+            .filter(|(_, block)| is_code(block.color()))
+            .map(|(id, _)| id);
+
+        let (covered, total) = code.fold((0, 0), |(covered, total), id| {
+            (covered + self.entered[id] as usize, total + 1)
+        });
+
+        (covered, total)
+    }
+
+    /// How many exits were taken, out of how many aren't blocked.
+    pub fn exits_covered(&self) -> (usize, usize) {
+        let covered = self.exits.intersection(&self.possible_exits).count();
+
+        (covered, self.possible_exits.len())
+    }
+
+    /// How many directions the DP or CC was turned in by `Pointer` and `Switch`
+    /// commands, out of how many directions those commands could have turned
+    /// them in.
+    pub fn branches_covered(&self) -> (usize, usize) {
+        let covered = self
+            .branches
+            .values()
+            .map(|outcomes| outcomes.count_ones() as usize)
+            .sum();
+        let total = self
+            .possible_branches
+            .values()
+            .map(|&command| outcome_count(command) as usize)
+            .sum();
+
+        (covered, total)
+    }
+
+    /// A summary of the coverage, as percentages.
+    pub fn summary(&self) -> String {
+        let mut summary = String::new();
+
+        let lines = [
+            ("blocks", self.blocks_covered()),
+            ("exits", self.exits_covered()),
+            ("branches", self.branches_covered()),
+        ];
+
+        for &(name, (covered, total)) in &lines {
+            let percentage = if total == 0 {
+                100.0
+            } else {
+                covered as f64 * 100.0 / total as f64
+            };
+
+            writeln!(
+                summary,
+                "{}: {}/{} ({:.1}%)",
+                name, covered, total, percentage
+            )
+            .unwrap();
+        }
+
+        summary
+    }
+
+    /// Draw the program, with colored blocks that were never entered crossed
+    /// out.
+    pub fn image(&self, program: &Program, scale: u32) -> RgbImage {
+        let mut canvas = Canvas::new(program, scale);
+
+        for (id, block) in self.blocks.iter() {
+            if is_code(block.color()) && !self.entered[id] {
+                for &codel in block.codels() {
+                    canvas.hatch_codel(codel, UNCOVERED_COLOR);
+                }
+
+                let codels = block.codels().iter().copied().collect();
+                canvas.outline(&codels, (scale / 8).max(1), UNCOVERED_COLOR);
+            }
+        }
+
+        canvas.into_image()
+    }
+
+    /// Write the coverage in a simple text format, which can be read back with
+    /// `read_data`.
+    ///
+    /// The first line is `program WIDTH HEIGHT HASH`, so that coverage of a
+    /// different program can't be read back. Every other line is one of:
+    ///
+    /// - `block ID`
+    /// - `exit ID DP CC`
+    /// - `branch ID DP CC COMMAND OUTCOMES`, where `OUTCOMES` is a bitmask of
+    ///   the directions the DP or CC was turned in.
+    pub fn to_data(&self) -> String {
+        let mut data = String::new();
+        writeln!(data, "program {}", self.fingerprint).unwrap();
+
+        for (id, _) in self.entered.iter().enumerate().filter(|(_, &e)| e) {
+            writeln!(data, "block {}", id).unwrap();
+        }
+
+        let mut exits = self.exits.iter().collect::<Vec<_>>();
+        exits.sort_by_key(|&&(id, dp, cc)| (id, direction_index(dp), cc as u8));
+
+        for &(id, dp, cc) in exits {
+            writeln!(data, "exit {} {:?} {:?}", id, dp, cc).unwrap();
+
+            if let Some(outcomes) = self.branches.get(&(id, dp, cc)) {
+                let command = self.possible_branches[&(id, dp, cc)];
+                writeln!(
+                    data,
+                    "branch {} {:?} {:?} {} {}",
+                    id,
+                    dp,
+                    cc,
+                    command.mnemonic(),
+                    outcomes
+                )
+                .unwrap();
+            }
+        }
+
+        data
+    }
+
+    /// Read coverage written by `to_data`, and merge it into this one.
+    ///
+    /// This fails if the data is for a different program, or says that an
+    /// exit executes a command that it doesn't.
+    pub fn read_data(&mut self, data: &str) -> Result<()> {
+        let mut checked = false;
+
+        for (index, line) in data.lines().enumerate() {
+            let syntax = |message: &str| ErrorKind::Syntax(index + 1, message.to_string());
+            let words = line.split_whitespace().collect::<Vec<_>>();
+
+            match words.first() {
+                None => continue,
+                Some(&"program") if !checked => {
+                    if words[1..].join(" ") != self.fingerprint {
+                        return Err(syntax("the coverage is for a different program").into());
+                    }
+
+                    checked = true;
+                    continue;
+                }
+                Some(_) if !checked => {
+                    return Err(syntax("expected `program` first").into());
+                }
+                Some(_) => {}
+            }
+
+            let id = match words.get(1).map(|id| id.parse::<BlockId>()) {
+                Some(Ok(id)) if id < self.blocks.len() => id,
+                None if words.is_empty() => continue,
+                _ => return Err(syntax("expected a block ID").into()),
+            };
+
+            let exit = || -> Result<Exit> {
+                let dp = match words.get(2) {
+                    Some(&"Up") => DirectionPointer::Up,
+                    Some(&"Right") => DirectionPointer::Right,
+                    Some(&"Down") => DirectionPointer::Down,
+                    Some(&"Left") => DirectionPointer::Left,
+                    _ => return Err(syntax("expected a direction").into()),
+                };
+                let cc = match words.get(3) {
+                    Some(&"Left") => CodelChooser::Left,
+                    Some(&"Right") => CodelChooser::Right,
+                    _ => return Err(syntax("expected a codel chooser").into()),
+                };
+
+                Ok((id, dp, cc))
+            };
+
+            match words[0] {
+                "block" => self.entered[id] = true,
+                "exit" => {
+                    self.exits.insert(exit()?);
+                }
+                "branch" => {
+                    let command = match words.get(4) {
+                        Some(&"pointer") => Command::Pointer,
+                        Some(&"switch") => Command::Switch,
+                        _ => return Err(syntax("expected `pointer` or `switch`").into()),
+                    };
+                    let outcomes = match words.get(5).map(|o| o.parse::<u8>()) {
+                        Some(Ok(outcomes)) if outcomes < 1 << outcome_count(command) => outcomes,
+                        _ => return Err(syntax("expected a bitmask of outcomes").into()),
+                    };
+
+                    let exit = exit()?;
+                    if self.possible_branches.get(&exit) != Some(&command) {
+                        let message = format!("that exit doesn't execute `{}`", command.mnemonic());
+                        return Err(syntax(&message).into());
+                    }
+
+                    *self.branches.entry(exit).or_insert(0) |= outcomes;
+                }
+                other => return Err(syntax(&format!("unknown entry `{}`", other)).into()),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Every exit of a colored block that isn't blocked.
+pub(crate) fn open_exits(program: &Program, blocks: &Blocks) -> HashSet<Exit> {
+    let mut exits = HashSet::new();
+
+    for (id, block) in blocks.iter() {
+        if !is_code(block.color()) {
+            continue;
+        }
+
+        for &(dp, cc) in &ALL_DIRECTIONS {
+            let exit = block.exit(dp, cc);
+            let open = dp
+                .offset(exit)
+                .and_then(|next| program.get(next))
+                .is_some_and(|color| color != Color::Black);
+
+            if open {
+                exits.insert((id, dp, cc));
+            }
+        }
+    }
+
+    exits
+}
+
+/// The exits that execute `Pointer` or `Switch`, and which of them they
+/// execute. Those always go straight into the next block, since sliding
+/// through white never executes anything.
+fn branch_exits(
+    program: &Program,
+    blocks: &Blocks,
+    exits: &HashSet<Exit>,
+) -> HashMap<Exit, Command> {
+    exits
+        .iter()
+        .filter_map(|&(id, dp, cc)| {
+            let block = &blocks[id];
+            let next = dp.offset(block.exit(dp, cc))?;
+            let command = Color::transition(block.color(), program.get(next)?)
+                .map(Command::from_transition)?;
+
+            match command {
+                Command::Pointer | Command::Switch => Some(((id, dp, cc), command)),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// How many ways `Pointer` or `Switch` can turn the DP or CC.
+fn outcome_count(command: Command) -> u8 {
+    if command == Command::Pointer {
+        4
+    } else {
+        2
+    }
+}
+
+/// The size of a program, and a hash of its colors, which doesn't change
+/// between versions of Rust.
+fn fingerprint(program: &Program) -> String {
+    // 64-bit FNV-1a.
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+
+    for (_, color) in program.codels() {
+        let (r, g, b) = color.to_rgb();
+
+        for byte in [r, g, b] {
+            hash = (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    let (width, height) = program.size();
+    format!("{} {} {:016x}", width, height, hash)
+}
+
+/// Every combination of DP and CC.
+pub(crate) const ALL_DIRECTIONS: [(DirectionPointer, CodelChooser); 8] = [
+    (DirectionPointer::Right, CodelChooser::Left),
+    (DirectionPointer::Right, CodelChooser::Right),
+    (DirectionPointer::Down, CodelChooser::Left),
+    (DirectionPointer::Down, CodelChooser::Right),
+    (DirectionPointer::Left, CodelChooser::Left),
+    (DirectionPointer::Left, CodelChooser::Right),
+    (DirectionPointer::Up, CodelChooser::Left),
+    (DirectionPointer::Up, CodelChooser::Right),
+];
+
+/// Whether a block of this color is part of the code, rather than a wall or
+/// empty space.
+pub(crate) fn is_code(color: Color) -> bool {
+    color != Color::Black && color != Color::White
+}
+
+/// The number of clockwise turns from pointing up.
+pub(crate) fn direction_index(dp: DirectionPointer) -> u8 {
+    match dp {
+        DirectionPointer::Up => 0,
+        DirectionPointer::Right => 1,
+        DirectionPointer::Down => 2,
+        DirectionPointer::Left => 3,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::{Hue::*, Lightness::*};
+    use crate::interpreter::Interpreter;
+
+    const B: Color = Color::Black;
+    const LR: Color = Color::Composite(Red, Light);
+    const DB: Color = Color::Composite(Blue, Dark);
+    const LY: Color = Color::Composite(Yellow, Light);
+    const NY: Color = Color::Composite(Yellow, Normal);
+
+    /// Read a number, and turn the DP by it with `Pointer`.
+    #[rustfmt::skip]
+    fn program() -> Program {
+        Program::new((4, 2), vec![
+            LR, DB, LY, B,
+            B,  B,  NY, B,
+        ])
+    }
+
+    fn run(input: &str) -> Coverage {
+        let program = program();
+        let mut interpreter = Interpreter::new(program.clone());
+        interpreter.feed_input(input);
+
+        let mut coverage = Coverage::new(&program);
+        let (trace, result) = Trace::record(&mut interpreter, Some(20));
+        result.unwrap();
+        coverage.record_trace(&trace);
+
+        coverage
+    }
+
+    #[test]
+    fn test_coverage_merge() {
+        let mut coverage = Coverage::new(&program());
+        assert_eq!(coverage.blocks_covered(), (0, 4));
+        assert_eq!(coverage.branches_covered(), (0, 12));
+
+        coverage.merge(&run("0"));
+        assert_eq!(coverage.blocks_covered(), (4, 4));
+        assert_eq!(coverage.branches_covered(), (1, 12));
+        assert!(coverage.exit_taken((0, DirectionPointer::Right, CodelChooser::Left)));
+
+        coverage.merge(&run("1"));
+        coverage.merge(&run("1"));
+        assert_eq!(coverage.branches_covered(), (2, 12));
+
+        let (covered, total) = coverage.exits_covered();
+        assert!(covered > 0 && covered < total);
+        assert!(coverage.summary().starts_with("blocks: 4/4 (100.0%)\n"));
+    }
+
+    #[test]
+    fn test_coverage_data() {
+        let coverage = run("1");
+        let data = coverage.to_data();
+
+        let mut read = Coverage::new(&program());
+        read.read_data(&data).unwrap();
+        assert_eq!(read.to_data(), data);
+        assert_eq!(read.summary(), coverage.summary());
+
+        let program_line = data.lines().next().unwrap();
+        let branch = data
+            .lines()
+            .find(|line| line.starts_with("branch"))
+            .unwrap();
+        let read_line = |line: &str| {
+            let mut read = Coverage::new(&program());
+            read.read_data(&format!("{}\n{}", program_line, line))
+        };
+
+        assert!(read_line("exit 0 Sideways Left").is_err());
+        assert!(read_line("block 100").is_err());
+        assert!(read.read_data("\n\n").is_ok());
+
+        // Only the outcomes of the command that the exit executes can be read.
+        assert!(read_line(&branch.replace("pointer 2", "pointer 15")).is_ok());
+        assert!(read_line(&branch.replace("pointer 2", "pointer 255")).is_err());
+        assert!(read_line(&branch.replace("pointer 2", "switch 1")).is_err());
+        assert!(read_line("branch 0 Right Left pointer 1").is_err());
+
+        // Coverage of another program can't be read.
+        assert!(read.read_data("block 0").is_err());
+        let other = Program::new((4, 2), vec![LR; 8]);
+        assert!(read.read_data(&Coverage::new(&other).to_data()).is_err());
+    }
+
+    #[test]
+    fn test_coverage_image() {
+        let coverage = Coverage::new(&program());
+        let image = coverage.image(&program(), 8);
+
+        // Nothing ran, so every colored block is marked.
+        assert_eq!(image.get_pixel(0, 0), &UNCOVERED_COLOR);
+        // Black isn't code.
+        assert_eq!(image.get_pixel(27, 3), &Rgb([0x00, 0x00, 0x00]));
+    }
+}
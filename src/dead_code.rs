@@ -0,0 +1,188 @@
+use std::fmt::Write;
+
+use image::{Rgb, RgbImage};
+
+use crate::blocks::{BlockId, Blocks};
+use crate::cfg::Cfg;
+use crate::coverage::{self, Exit};
+use crate::dialect::Dialect;
+use crate::program::Program;
+use crate::render::Canvas;
+
+/// The gray that unreachable blocks are mixed with.
+const DEAD_COLOR: (u8, u8, u8) = (0xa0, 0xa0, 0xa0);
+
+/// The parts of a program that can never run, whatever the input.
+///
+/// These are found by following every way the interpreter can go from the
+/// start, assuming `Pointer` and `Switch` can turn any way. Two things are
+/// found:
+///
+/// - Colored blocks that can never be entered. Only black and white are
+///   expected to be left out, so these are often decoration.
+/// - Exits of blocks that can be entered, which aren't blocked, but are never
+///   taken.
+#[derive(Debug, Clone)]
+pub struct DeadCode {
+    blocks: Blocks,
+    dead_blocks: Vec<BlockId>,
+    dead_exits: Vec<Exit>,
+}
+
+impl DeadCode {
+    pub fn new(program: &Program) -> Self {
+        DeadCode::with_dialect(program, Dialect::default())
+    }
+
+    /// Finds the dead code of a program that moves between color blocks the
+    /// way the dialect does.
+    pub fn with_dialect(program: &Program, dialect: Dialect) -> Self {
+        let cfg = Cfg::with_dialect(program, dialect);
+        let reachable = cfg.reachable_blocks();
+        let blocks = cfg.blocks().clone();
+
+        let dead_blocks = blocks
+            .iter()
+            .filter(|(id, block)| coverage::is_code(block.color()) && !reachable.contains(id))
+            .map(|(id, _)| id)
+            .collect();
+
+        let taken = cfg
+            .states()
+            .iter()
+            .flat_map(|&state| cfg.edges(state).iter().map(move |edge| (state.0, edge)))
+            .map(|(id, edge)| (id, edge.dp, edge.cc))
+            .collect::<Vec<_>>();
+
+        let mut dead_exits = coverage::open_exits(program, &blocks)
+            .into_iter()
+            .filter(|exit| reachable.contains(&exit.0) && !taken.contains(exit))
+            .collect::<Vec<_>>();
+        dead_exits.sort_by_key(|&(id, dp, cc)| (id, coverage::direction_index(dp), cc as u8));
+
+        DeadCode {
+            blocks,
+            dead_blocks,
+            dead_exits,
+        }
+    }
+
+    /// The colored blocks that can never be entered, in order.
+    pub fn blocks(&self) -> &[BlockId] {
+        &self.dead_blocks
+    }
+
+    /// The exits of reachable blocks that are never taken, in order.
+    pub fn exits(&self) -> &[Exit] {
+        &self.dead_exits
+    }
+
+    /// A line describing each unreachable block, and then each exit that's
+    /// never taken.
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+
+        for &id in &self.dead_blocks {
+            let block = &self.blocks[id];
+            let (x, y) = block.origin();
+
+            writeln!(
+                report,
+                "({}, {}): block {} ({}, {} codel{}) is never entered",
+                x,
+                y,
+                id,
+                block.color(),
+                block.size(),
+                if block.size() == 1 { "" } else { "s" }
+            )
+            .unwrap();
+        }
+
+        for &(id, dp, cc) in &self.dead_exits {
+            let (x, y) = self.blocks[id].exit(dp, cc);
+
+            writeln!(
+                report,
+                "({}, {}): exit of block {} with DP {} and CC {} is never taken",
+                x, y, id, dp, cc
+            )
+            .unwrap();
+        }
+
+        report
+    }
+
+    /// Draw the program, with the blocks that can never be entered grayed
+    /// out.
+    pub fn image(&self, program: &Program, scale: u32) -> RgbImage {
+        let mut canvas = Canvas::new(program, scale);
+        let mix = |a: u8, b: u8| ((a as u16 + b as u16) / 2) as u8;
+
+        for &id in &self.dead_blocks {
+            let block = &self.blocks[id];
+            let (r, g, b) = block.color().to_rgb();
+            let (dead_r, dead_g, dead_b) = DEAD_COLOR;
+
+            // Average the channels first, so every hue ends up gray.
+            let gray = ((r as u16 + g as u16 + b as u16) / 3) as u8;
+            let color = Rgb([mix(gray, dead_r), mix(gray, dead_g), mix(gray, dead_b)]);
+
+            for &codel in block.codels() {
+                canvas.fill_codel(codel, color);
+            }
+        }
+
+        canvas.into_image()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::{Color, Hue::*, Lightness::*};
+    use crate::interpreter::{CodelChooser, DirectionPointer};
+
+    const B: Color = Color::Black;
+    const LR: Color = Color::Composite(Red, Light);
+    const NR: Color = Color::Composite(Red, Normal);
+    const DR: Color = Color::Composite(Red, Dark);
+    const LY: Color = Color::Composite(Yellow, Light);
+    const LG: Color = Color::Composite(Green, Light);
+
+    /// Push 3, push 2, subtract, and output the result as a number, with a
+    /// green block in the corner that the interpreter never gets to.
+    #[rustfmt::skip]
+    fn program() -> Program {
+        Program::new((9, 3), vec![
+            LR, LR, LR, NR, NR, DR, LY, NR, B,
+            B,  B,  B,  B,  B,  B,  NR, NR, B,
+            LG, B,  B,  B,  B,  B,  B,  B,  B,
+        ])
+    }
+
+    #[test]
+    fn test_dead_code() {
+        let program = program();
+        let dead_code = DeadCode::new(&program);
+
+        let green = dead_code.blocks.id_at((0, 2)).unwrap();
+        assert_eq!(dead_code.blocks(), [green]);
+        assert!(dead_code.report().starts_with(&format!(
+            "(0, 2): block {} (light green, 1 codel) is never entered\n",
+            green
+        )));
+
+        // The CC is never switched, so it always points left.
+        let exits = dead_code.exits();
+        assert!(exits.contains(&(0, DirectionPointer::Right, CodelChooser::Right)));
+        assert!(!exits.contains(&(0, DirectionPointer::Right, CodelChooser::Left)));
+        // Exits of blocks that are never entered aren't listed.
+        assert!(exits.iter().all(|&(id, _, _)| id != green));
+
+        let image = dead_code.image(&program, 2);
+        let &Rgb([r, g, b]) = image.get_pixel(0, 4);
+        assert!(r == g && g == b);
+        assert_eq!(image.get_pixel(0, 0), &Rgb([0xff, 0xc0, 0xc0]));
+    }
+}
@@ -0,0 +1,345 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::cfg::{Cfg, Edge};
+use crate::command::Command;
+use crate::util::Coords;
+
+/// How many times a state's depth can grow before it's assumed to grow
+/// forever, so that loops that push values are analyzed in finite time.
+const WIDEN_AFTER: u32 = 8;
+
+/// The range of depths the stack can have. `max` is `None` if the stack can
+/// grow without limit.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Depth {
+    pub min: usize,
+    pub max: Option<usize>,
+}
+
+impl Depth {
+    /// The smallest range that contains both.
+    fn join(self, other: Depth) -> Depth {
+        Depth {
+            min: self.min.min(other.min),
+            max: self.max.zip(other.max).map(|(a, b)| a.max(b)),
+        }
+    }
+
+    /// The range after executing a command with every depth in this range.
+    fn after(self, command: Command) -> Depth {
+        let (needs, fewest, most) = effect(command);
+
+        // With too few values, the command is ignored.
+        if self.max.is_some_and(|max| max < needs) {
+            return self;
+        }
+
+        let executed = Depth {
+            min: self.min.max(needs) - needs + fewest,
+            max: self.max.map(|max| max - needs + most),
+        };
+
+        if self.min >= needs {
+            executed
+        } else {
+            let ignored = Depth {
+                min: self.min,
+                max: Some(needs - 1),
+            };
+
+            ignored.join(executed)
+        }
+    }
+}
+
+impl fmt::Display for Depth {
+    /// Write the range like "2", "0..=3" or "1 or more".
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.max {
+            Some(max) if max == self.min => write!(f, "{}", max),
+            Some(max) => write!(f, "{}..={}", self.min, max),
+            None => write!(f, "{} or more", self.min),
+        }
+    }
+}
+
+/// How many values a command needs, and the fewest and most values it leaves
+/// in their place when it's executed with enough.
+fn effect(command: Command) -> (usize, usize, usize) {
+    use crate::command::Command::*;
+
+    match command {
+        NoOp => (0, 0, 0),
+        Push => (0, 1, 1),
+        Pop | Pointer | Switch | IntOut => (1, 0, 0),
+        Not => (1, 1, 1),
+        Duplicate => (1, 2, 2),
+        // Writing an invalid character leaves it on the stack.
+        CharOut => (1, 0, 1),
+        Add | Subtract | Multiply | Greater => (2, 1, 1),
+        // Dividing by zero is ignored.
+        Divide | Mod => (2, 1, 2),
+        // A roll is ignored if the depth is invalid, and otherwise both values
+        // are used up.
+        Roll => (2, 0, 2),
+        // Reading at the end of the input is ignored.
+        IntIn | CharIn => (0, 0, 1),
+    }
+}
+
+/// A command that runs with fewer values on the stack than it needs, so
+/// it's ignored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Underflow {
+    /// The codel that the command's move leaves from.
+    pub at: Coords,
+    pub command: Command,
+    /// The depths the stack can have when the command runs.
+    pub depth: Depth,
+    /// Whether this happens every time the command runs, rather than only
+    /// sometimes.
+    pub always: bool,
+}
+
+impl fmt::Display for Underflow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (x, y) = self.at;
+        let (needs, _, _) = effect(self.command);
+
+        write!(
+            f,
+            "({}, {}): `{}` {}: it needs {} value{}, but the stack has {}",
+            x,
+            y,
+            self.command.mnemonic(),
+            if self.always {
+                "always underflows"
+            } else {
+                "may underflow"
+            },
+            needs,
+            if needs == 1 { "" } else { "s" },
+            self.depth
+        )
+    }
+}
+
+/// What's known about the stack in a state.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Facts {
+    depth: Depth,
+    /// Whether the top of the stack is 0 or 1, if there is one. That's the
+    /// case after `Not` and `Greater`, which is how most programs choose
+    /// between two ways with `Pointer` and `Switch`.
+    boolean: bool,
+}
+
+impl Facts {
+    fn join(self, other: Facts) -> Facts {
+        Facts {
+            depth: self.depth.join(other.depth),
+            boolean: self.boolean && other.boolean,
+        }
+    }
+
+    fn after(self, command: Option<Command>) -> Facts {
+        let depth = self.depth;
+
+        match command {
+            None | Some(Command::NoOp) => self,
+            Some(command) => Facts {
+                depth: depth.after(command),
+                boolean: match command {
+                    Command::Not => true,
+                    // With one value, `Greater` is ignored.
+                    Command::Greater => depth.min >= 2 || depth.max == Some(0),
+                    _ => false,
+                },
+            },
+        }
+    }
+
+    /// Whether an edge can be taken.
+    fn allows(self, edge: &Edge) -> bool {
+        !self.boolean || edge.branch.unwrap_or(0) < 2
+    }
+}
+
+/// Find the commands that can run with too few values on the stack, by
+/// working out the range of depths the stack can have in every state of the
+/// control flow graph.
+///
+/// `Pointer` and `Switch` are assumed to turn any way, unless the value they
+/// use comes straight from `Not` or `Greater`, so some underflows may not
+/// really be possible. They're sorted by position, with at most one for each
+/// codel and command.
+pub fn find_underflows(cfg: &Cfg) -> Vec<Underflow> {
+    let states = cfg.states();
+    if states.is_empty() {
+        return Vec::new();
+    }
+
+    let mut facts = vec![None; states.len()];
+    let mut updates = vec![0; states.len()];
+    facts[0] = Some(Facts {
+        depth: Depth {
+            min: 0,
+            max: Some(0),
+        },
+        boolean: false,
+    });
+
+    let mut pending = vec![0];
+    while let Some(index) = pending.pop() {
+        let known: Facts = facts[index].unwrap();
+
+        for edge in cfg.edges(states[index]) {
+            if !known.allows(edge) {
+                continue;
+            }
+
+            let after = known.after(edge.command);
+            let target = cfg.index(edge.to).unwrap(); // Every target is reachable.
+
+            let mut joined = facts[target].map_or(after, |old: Facts| old.join(after));
+            if Some(joined) == facts[target] {
+                continue;
+            }
+
+            updates[target] += 1;
+            if updates[target] > WIDEN_AFTER {
+                joined.depth.max = None;
+            }
+
+            facts[target] = Some(joined);
+            pending.push(target);
+        }
+    }
+
+    let mut underflows = BTreeMap::new();
+
+    for (index, &state) in states.iter().enumerate() {
+        // Some states are only reachable through branches that can't happen.
+        let known = match facts[index] {
+            Some(known) => known,
+            None => continue,
+        };
+        let depth = known.depth;
+
+        for edge in cfg.edges(state) {
+            let command = match edge.command {
+                Some(command) if known.allows(edge) => command,
+                _ => continue,
+            };
+            let (needs, _, _) = effect(command);
+
+            if depth.min >= needs {
+                continue;
+            }
+
+            let at = cfg.blocks()[state.0].exit(edge.dp, edge.cc);
+            let underflow = Underflow {
+                at,
+                command,
+                depth,
+                always: depth.max.is_some_and(|max| max < needs),
+            };
+
+            underflows
+                .entry((at.1, at.0, command.mnemonic()))
+                .and_modify(|existing: &mut Underflow| {
+                    existing.depth = existing.depth.join(depth);
+                    existing.always &= underflow.always;
+                })
+                .or_insert(underflow);
+        }
+    }
+
+    underflows.into_values().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    #[test]
+    fn test_depth_after() {
+        let depth = |min, max| Depth { min, max };
+
+        assert_eq!(depth(0, Some(0)).after(Command::Push), depth(1, Some(1)));
+        assert_eq!(depth(0, Some(0)).after(Command::Add), depth(0, Some(0)));
+        assert_eq!(depth(1, Some(3)).after(Command::Add), depth(1, Some(2)));
+        assert_eq!(depth(3, None).after(Command::Roll), depth(1, None));
+        assert_eq!(depth(2, Some(4)).after(Command::Divide), depth(1, Some(4)));
+        assert_eq!(depth(0, Some(2)).after(Command::IntIn), depth(0, Some(3)));
+        assert_eq!(
+            depth(0, Some(5)).after(Command::Duplicate),
+            depth(0, Some(6))
+        );
+        assert_eq!(depth(0, Some(5)).to_string(), "0..=5");
+    }
+
+    #[test]
+    fn test_find_underflows() {
+        let underflows = |source| find_underflows(&Cfg::new(&assemble(source).unwrap()));
+
+        assert!(underflows("push 1\npush 2\nadd\nout num").is_empty());
+
+        // The ignored `add` leaves the value for `out num`.
+        let found = underflows("push 1\nadd\nout num\nout num");
+        assert_eq!(found.len(), 2);
+        assert!(found.iter().all(|underflow| underflow.always));
+        assert_eq!(found[0].command, Command::Add);
+        assert_eq!(found[0].depth.to_string(), "1");
+        assert_eq!(found[1].command, Command::IntOut);
+        assert_eq!(found[1].depth.to_string(), "0");
+
+        // Reading a number might fail at the end of the input.
+        let found = underflows("in num\nout num");
+        assert_eq!(found.len(), 1);
+        assert!(!found[0].always);
+
+        // A loop that keeps pushing doesn't stop the analysis.
+        let found = underflows("loop:\npush 1\ndup\njnz loop");
+        assert!(found.is_empty());
+    }
+
+    #[test]
+    fn test_find_underflows_sound() {
+        use crate::differential::{self, INPUT};
+        use crate::interpreter::Interpreter;
+
+        let mut programs = differential::command_programs();
+        programs.extend(differential::random_programs(100));
+
+        for program in programs {
+            let underflows = find_underflows(&Cfg::new(&program));
+            let mut interpreter = Interpreter::new(program);
+            interpreter.feed_input(INPUT);
+
+            loop {
+                let depth = interpreter.stack().len();
+                let step = match interpreter.step() {
+                    Some(step) => step.unwrap(),
+                    None => break,
+                };
+                let command = match step.command {
+                    Some(command) => command,
+                    None => continue,
+                };
+
+                let (needs, _, _) = effect(command);
+                let found = underflows
+                    .iter()
+                    .find(|underflow| underflow.at == step.exit && underflow.command == command);
+
+                match found {
+                    Some(underflow) => assert!(depth < needs || !underflow.always),
+                    None => assert!(depth >= needs),
+                }
+            }
+        }
+    }
+}
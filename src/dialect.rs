@@ -0,0 +1,96 @@
+/// What to do when a command can't be executed the normal way.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Fault {
+    /// Leave the stack as it was, as if the command wasn't there.
+    Ignore,
+    /// Pop the values the command would have used, and do nothing else.
+    Discard,
+    /// Stop with `ErrorKind::CommandError`.
+    Error,
+}
+
+/// What `CharIn` does when there's no input left.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AtEof {
+    /// Push nothing.
+    Ignore,
+    /// Push this value instead of a character, like -1 or 0.
+    Push(i64),
+    /// Stop with `ErrorKind::CommandError`.
+    Error,
+}
+
+/// What happens when the top left codel, where the interpreter starts, is
+/// white.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StartOnWhite {
+    /// Slide out of it, the same way as out of any other white codel.
+    Slide,
+    /// Halt straight away.
+    Halt,
+    /// Stop with `ErrorKind::StartOnWhite`.
+    Error,
+}
+
+/// The choices an interpreter makes where the Piet specification is
+/// ambiguous, or says nothing.
+///
+/// Interpreters disagree about these, so programs written for one of them
+/// might need its choices to run correctly. `Dialect::NPIET` is the default.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Dialect {
+    /// What `Roll` does when the depth is negative.
+    pub negative_roll: Fault,
+    /// What `Divide` and `Mod` do when dividing by zero.
+    pub division_by_zero: Fault,
+    /// What `CharIn` does at the end of the input.
+    pub char_in_at_eof: AtEof,
+    /// How many times sliding through white can be blocked before the program
+    /// halts. With `None`, it only halts when it gets back to a codel it
+    /// already slid through, going the same way.
+    pub white_retry_limit: Option<u32>,
+    /// What happens when the interpreter starts on a white codel.
+    pub start_on_white: StartOnWhite,
+}
+
+impl Dialect {
+    /// The choices npiet makes: anything that can't be executed is ignored,
+    /// and sliding through white only halts when it goes in circles.
+    pub const NPIET: Dialect = Dialect {
+        negative_roll: Fault::Ignore,
+        division_by_zero: Fault::Ignore,
+        char_in_at_eof: AtEof::Ignore,
+        white_retry_limit: None,
+        start_on_white: StartOnWhite::Slide,
+    };
+
+    /// Stop with an error whenever the specification doesn't say what should
+    /// happen, to find out whether a program depends on it. Sliding through
+    /// white halts after being blocked eight times, like leaving a color
+    /// block does.
+    pub const STRICT: Dialect = Dialect {
+        negative_roll: Fault::Error,
+        division_by_zero: Fault::Error,
+        char_in_at_eof: AtEof::Error,
+        white_retry_limit: Some(7),
+        start_on_white: StartOnWhite::Error,
+    };
+
+    /// Whether commands are executed the way npiet executes them, whatever
+    /// the choices about moving are.
+    ///
+    /// The compilers only support dialects like this. The moves in the
+    /// control flow graph can follow any dialect, but the code they generate
+    /// always executes commands the way npiet does.
+    pub fn executes_like_npiet(&self) -> bool {
+        self.negative_roll == Fault::Ignore
+            && self.division_by_zero == Fault::Ignore
+            && self.char_in_at_eof == AtEof::Ignore
+    }
+}
+
+impl Default for Dialect {
+    fn default() -> Self {
+        Dialect::NPIET
+    }
+}
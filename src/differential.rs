@@ -0,0 +1,135 @@
+//! Programs and input for checking that other ways of running a program
+//! behave exactly like `Interpreter`.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+
+use crate::asm::assemble;
+use crate::color::{Color, Hue, Lightness};
+use crate::generate::print_text;
+use crate::interpreter::Interpreter;
+use crate::program::Program;
+
+/// Input that exercises the corner cases of `IntIn` and `CharIn`.
+pub const INPUT: &str = " 12\t-7 +3 x✓ 99999999999999999999 -9223372036854775808\u{3000}5 🦀";
+
+/// Programs that use every command, including the cases where they're
+/// ignored. They all halt, with any input.
+pub fn command_programs() -> Vec<Program> {
+    let sources = [
+        // Arithmetic, including overflow and division by zero.
+        "push 9223372036854775807\npush 1\nadd\nout num\n\
+         push -9223372036854775808\ndup\npush -1\ndiv\nout num\npush -1\nmod\nout num\n\
+         push -7\npush 3\nmod\nout num\npush 7\npush -3\nmod\nout num\n\
+         push -7\npush 2\ndiv\nout num\npush 5\npush 0\ndiv\nout num\nout num\n\
+         push 3\npush 2\ngt\nout num\npush 0\nnot\nout num\nadd\nout num",
+        // Rolls, including ones that are ignored.
+        "push 1\npush 2\npush 3\npush 4\npush 3\npush -1\nroll\n\
+         push 2\npush 5\nroll\npush 9\npush 1\nroll\npush -1\npush 1\nroll\n\
+         out num\nout num\nout num\nout num\nout num\nout num\nout num",
+        // Characters that can't be written stay on the stack.
+        "push -5\nout char\npush 55296\nout char\npush 4294967361\nout char\n\
+         push 129408\nout char\nout num\nout num\npop\npop\ndup\nout num",
+        // Read numbers until that fails, then echo the rest.
+        "numbers:\npush 0\nin num\ndup\njz chars\nout num\npop\njmp numbers\n\
+         chars:\npop\npush 0\nin char\ndup\njz end\nout char\npop\njmp chars\nend:",
+    ];
+
+    let mut programs = sources
+        .iter()
+        .map(|source| assemble(source).unwrap())
+        .collect::<Vec<_>>();
+    programs.push(print_text("Hello, wörld! 🦀").unwrap());
+
+    programs
+}
+
+/// `count` small random programs that halt with `INPUT`. They're the same
+/// every time.
+pub fn random_programs(count: usize) -> Vec<Program> {
+    // Finding programs that halt takes a while, so they're shared by every
+    // test.
+    static PROGRAMS: OnceLock<Vec<Program>> = OnceLock::new();
+
+    let programs = PROGRAMS.get_or_init(|| generate_random_programs(100));
+    assert!(count <= programs.len());
+
+    programs[..count].to_vec()
+}
+
+fn generate_random_programs(count: usize) -> Vec<Program> {
+    let mut colors = vec![Color::White, Color::Black];
+    for &hue in &Hue::ALL {
+        for &lightness in &Lightness::ALL {
+            colors.push(Color::Composite(hue, lightness));
+        }
+    }
+
+    // A small xorshift generator.
+    let mut seed = 0x2545_f491_4f6c_dd1d_u64;
+    let mut random = |limit: usize| {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        (seed % limit as u64) as usize
+    };
+
+    let mut programs = Vec::new();
+
+    while programs.len() < count {
+        let (width, height) = (2 + random(6), 1 + random(6));
+
+        // Repeating the previous color makes bigger blocks.
+        let mut image: Vec<Color> = Vec::new();
+        for _ in 0..width * height {
+            let color = match image.last() {
+                Some(&last) if random(3) == 0 => last,
+                _ => colors[random(colors.len())],
+            };
+            image.push(color);
+        }
+
+        let program = Program::new((width, height), image);
+        if interpret(&program, INPUT).is_some() {
+            programs.push(program);
+        }
+    }
+
+    programs
+}
+
+/// The output of the interpreter, or `None` if it doesn't halt in time.
+pub fn interpret(program: &Program, input: &str) -> Option<String> {
+    let mut interpreter = Interpreter::new(program.clone());
+    interpreter.feed_input(input);
+
+    for _ in 0..5_000 {
+        match interpreter.step() {
+            Some(result) => {
+                result.unwrap();
+            }
+            None => return Some(interpreter.output().to_string()),
+        }
+    }
+
+    None
+}
+
+/// A fresh directory to build things in, so tests can run in parallel.
+pub fn scratch_dir(name: &str) -> PathBuf {
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    let dir = env::temp_dir().join(format!(
+        "piet-{}-{}-{}",
+        name,
+        process::id(),
+        COUNT.fetch_add(1, Ordering::SeqCst)
+    ));
+    fs::create_dir_all(&dir).unwrap();
+
+    dir
+}
@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::cfg::{Cfg, Edge, State};
+use crate::command::Command;
+
+/// Write a program as a textual listing, one command per line.
+///
+/// Every state that can be jumped to gets a label, with a comment describing
+/// its block. Straight-line moves are listed in order, and each run ends in
+/// `halt`, a `jmp`, or a `pointer` or `switch` with a target for each value of
+/// the top of the stack, modulo 4 or 2. Moves that are blocked, or that slide
+/// through white, get a comment saying so.
+pub fn disassemble(cfg: &Cfg) -> String {
+    let states = cfg.states();
+    let labels = cfg
+        .leaders()
+        .into_iter()
+        .enumerate()
+        .map(|(number, index)| {
+            let label = if index == 0 {
+                "start".to_string()
+            } else {
+                format!("L{}", number)
+            };
+
+            (states[index], label)
+        })
+        .collect::<HashMap<_, _>>();
+
+    let mut listing = String::new();
+
+    for &leader in states.iter().filter(|state| labels.contains_key(state)) {
+        if !listing.is_empty() {
+            writeln!(listing).unwrap();
+        }
+
+        writeln!(
+            listing,
+            "{}:{:width$}; {}",
+            labels[&leader],
+            "",
+            describe(cfg, leader),
+            width = 12usize.saturating_sub(labels[&leader].len() + 1),
+        )
+        .unwrap();
+
+        let mut state = leader;
+
+        loop {
+            let edges = cfg.edges(state);
+            let edge = match edges.first() {
+                Some(edge) => edge,
+                None => {
+                    writeln!(listing, "    halt").unwrap();
+                    break;
+                }
+            };
+
+            write_notes(&mut listing, state, edge);
+
+            if edges.len() > 1 {
+                let targets = edges
+                    .iter()
+                    .map(|edge| labels[&edge.to].as_str())
+                    .collect::<Vec<_>>();
+                let command = edge.command.unwrap(); // Only commands can branch.
+
+                writeln!(
+                    listing,
+                    "    {} [{}]",
+                    command.mnemonic(),
+                    targets.join(", ")
+                )
+                .unwrap();
+                break;
+            }
+
+            match edge.command {
+                Some(Command::Push) => {
+                    let size = cfg.blocks()[state.0].size();
+                    writeln!(listing, "    push {}", size).unwrap();
+                }
+                Some(command) => {
+                    writeln!(listing, "    {}", command.mnemonic()).unwrap();
+                }
+                None => {}
+            }
+
+            match labels.get(&edge.to) {
+                Some(label) => {
+                    writeln!(listing, "    jmp {}", label).unwrap();
+                    break;
+                }
+                None => state = edge.to,
+            }
+        }
+    }
+
+    listing
+}
+
+/// Comments for a move that doesn't go straight to the next block.
+fn write_notes(listing: &mut String, (_, dp, cc): State, edge: &Edge) {
+    if edge.dp != dp || edge.cc != cc {
+        writeln!(
+            listing,
+            "    ; blocked {} times, leaving {} {}",
+            edge.retries, edge.dp, edge.cc
+        )
+        .unwrap();
+    }
+
+    if edge.slide > 0 {
+        writeln!(listing, "    ; slide over {} white codels", edge.slide).unwrap();
+    }
+}
+
+/// A description of a state, like "block 3 at (5, 0), dark red, right left".
+fn describe(cfg: &Cfg, (id, dp, cc): State) -> String {
+    let block = &cfg.blocks()[id];
+    let (x, y) = block.origin();
+
+    format!(
+        "block {} at ({}, {}), {}, {} {}",
+        id,
+        x,
+        y,
+        block.color(),
+        dp,
+        cc
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::{Color, Hue::*, Lightness::*};
+    use crate::program::Program;
+
+    const B: Color = Color::Black;
+    const LR: Color = Color::Composite(Red, Light);
+    const NR: Color = Color::Composite(Red, Normal);
+    const DR: Color = Color::Composite(Red, Dark);
+    const LY: Color = Color::Composite(Yellow, Light);
+    const DB: Color = Color::Composite(Blue, Dark);
+    const NY: Color = Color::Composite(Yellow, Normal);
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_disassemble_straight_line() {
+        let program = Program::new((9, 2), vec![
+            LR, LR, LR, NR, NR, DR, LY, NR, B,
+            B,  B,  B,  B,  B,  B,  NR, NR, B,
+        ]);
+        let listing = disassemble(&Cfg::new(&program));
+
+        assert_eq!(listing, "\
+start:      ; block 0 at (0, 0), light red, right left
+    push 3
+    push 2
+    sub
+    out num
+    halt
+");
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_disassemble_branches() {
+        let program = Program::new((4, 2), vec![
+            LR, DB, LY, B,
+            B,  B,  NY, B,
+        ]);
+        let listing = disassemble(&Cfg::new(&program));
+
+        assert!(listing.starts_with("start:"));
+        assert!(listing.contains("    in num\n    jmp L1\n"));
+        assert!(listing.contains("\n    pointer [L2, L3, L4, L5]\n"));
+        assert!(listing.contains("\n    switch [L8, L9]\n"));
+        assert!(listing.contains("    ; blocked 2 times, leaving down right\n    push 1\n"));
+    }
+}
@@ -1,6 +1,16 @@
+// The code that `error_chain!` generates for foreign links uses the deprecated
+// `Error::description` and `Error::cause`.
+#![allow(deprecated)]
+
+use crate::blocks::BlockId;
 use crate::command::Command;
 
 error_chain! {
+    foreign_links {
+        Image(image::ImageError);
+        Io(std::io::Error);
+    }
+
     errors {
         EmptyStack {
             description("the stack was popped, but it is empty")
@@ -10,5 +20,45 @@ error_chain! {
             description("there was an error while executing a command")
             display("there was an error executing command: {:?}", command)
         }
+
+        StartOnWhite {
+            description("the program starts on a white codel")
+        }
+
+        TransparentPixel(x: u32, y: u32) {
+            description("the image has a transparent pixel")
+            display("the pixel at ({}, {}) is transparent", x, y)
+        }
+
+        UnknownColor(x: u32, y: u32, rgb: (u8, u8, u8)) {
+            description("the image has a color that isn't in the palette")
+            display(
+                "the pixel at ({}, {}) is #{:02x}{:02x}{:02x}, which isn't a Piet color",
+                x, y, rgb.0, rgb.1, rgb.2
+            )
+        }
+
+        Syntax(line: usize, message: String) {
+            description("there was a syntax error")
+            display("syntax error on line {}: {}", line, message)
+        }
+
+        InfiniteLoop(length: u64, blocks: Vec<BlockId>) {
+            description("the program is stuck in a loop")
+            display(
+                "the program loops forever without input or output, repeating every {} steps through blocks {:?}",
+                length, blocks
+            )
+        }
+
+        UnsupportedDialect {
+            description("the dialect executes commands differently from npiet")
+            display("only programs that execute commands the way npiet does can be compiled")
+        }
+
+        OutputMismatch(expected: String, actual: String) {
+            description("a generated program printed the wrong output")
+            display("the generated program printed {:?} instead of {:?}", actual, expected)
+        }
     }
 }
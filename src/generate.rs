@@ -0,0 +1,106 @@
+use std::fmt::Write;
+
+use crate::asm::assemble;
+use crate::errors::*;
+use crate::interpreter::Interpreter;
+use crate::program::Program;
+use crate::synth::{Cost, Synthesizer};
+
+/// Generate a program that prints `text`.
+///
+/// Each character code is calculated from small numbers, or from the
+/// character before it, whichever takes the fewest codels. The program is run
+/// before it's returned, to check that it really prints `text`.
+pub fn print_text(text: &str) -> Result<Program> {
+    let program = assemble(&print_text_source(text))?;
+
+    let mut interpreter = Interpreter::new(program.clone());
+    let (width, height) = program.size();
+    let max_steps = (width * height) as u64;
+
+    while interpreter.steps() < max_steps {
+        match interpreter.step() {
+            Some(result) => {
+                result?;
+            }
+            None => break,
+        }
+    }
+
+    if interpreter.output() != text || !interpreter.is_halted() {
+        return Err(
+            ErrorKind::OutputMismatch(text.to_string(), interpreter.output().to_string()).into(),
+        );
+    }
+
+    Ok(program)
+}
+
+/// The assembly source of a program that prints `text`, for `assemble`.
+pub fn print_text_source(text: &str) -> String {
+    let mut source = String::new();
+    let mut synthesizer = Synthesizer::new(Cost::Area);
+    let mut previous: Option<i64> = None;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let code = i64::from(u32::from(c));
+        let fresh = vec![format!("push {}", code)];
+
+        // The last character is kept on the stack, so the next one can be
+        // calculated from it. `assemble` works out how to push each number,
+        // with the same costs as here.
+        let lines = match previous {
+            None => fresh,
+            Some(previous) => {
+                let difference = code - previous;
+                let (adjust_cost, adjust) = if difference == 0 {
+                    (0, vec![])
+                } else {
+                    let operation = if difference > 0 { "add" } else { "sub" };
+                    (
+                        synthesizer.cost(difference.abs()) + 1,
+                        vec![format!("push {}", difference.abs()), operation.to_string()],
+                    )
+                };
+
+                let replace_cost = synthesizer.cost(code) + 1;
+
+                if adjust_cost <= replace_cost {
+                    adjust
+                } else {
+                    std::iter::once("pop".to_string()).chain(fresh).collect()
+                }
+            }
+        };
+
+        writeln!(source, "; {:?}", c).unwrap();
+        for line in lines {
+            writeln!(source, "{}", line).unwrap();
+        }
+        if chars.peek().is_some() {
+            writeln!(source, "dup").unwrap();
+        }
+        writeln!(source, "out char").unwrap();
+
+        previous = Some(code);
+    }
+
+    source
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_print_text() {
+        for text in &["Hello, world!", "", "aaaa", "\0", "héllo ✓ 🦀"] {
+            // `print_text` checks the output itself.
+            print_text(text).unwrap();
+        }
+
+        let (width, _) = print_text("Hello, world!").unwrap().size();
+        assert!(width < 150, "the program is {} codels wide", width);
+    }
+}
@@ -0,0 +1,181 @@
+use std::fmt::Write;
+
+use image::ImageFormat;
+
+use crate::color::{Color, Hue, Lightness};
+use crate::errors::*;
+use crate::load::LoadOptions;
+use crate::program::Program;
+
+impl Program {
+    /// Read a program from the text grid format.
+    ///
+    /// Every line is a row of codels, written as two-letter codes separated by
+    /// whitespace. The first letter is the lightness: `l`ight, `n`ormal or
+    /// `d`ark. The second is the hue: `r`ed, `y`ellow, `g`reen, `c`yan, `b`lue
+    /// or `m`agenta. White is `wh` and black is `bk`. Everything after a `;` is
+    /// a comment, and lines without codels are skipped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use piet::{Color, Hue::*, Lightness::*, Program};
+    ///
+    /// let program = Program::from_grid("lr nr ; push 1\nbk wh").unwrap();
+    /// assert_eq!(program.size(), (2, 2));
+    /// assert_eq!(program.get((1, 0)), Some(Color::Composite(Red, Normal)));
+    /// ```
+    pub fn from_grid(source: &str) -> Result<Program> {
+        let mut width = None;
+        let mut colors = Vec::new();
+        let mut height = 0;
+
+        for (index, line) in source.lines().enumerate() {
+            let number = index + 1;
+            let syntax = |message: String| -> Error { ErrorKind::Syntax(number, message).into() };
+
+            let line = line.split(';').next().unwrap_or("");
+            let row = line
+                .split_whitespace()
+                .map(|code| {
+                    from_code(code).ok_or_else(|| syntax(format!("unknown color `{}`", code)))
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            if row.is_empty() {
+                continue;
+            }
+
+            match width {
+                Some(width) if width != row.len() => {
+                    return Err(syntax(format!(
+                        "expected {} codels, like the first row, but found {}",
+                        width,
+                        row.len()
+                    )));
+                }
+                _ => width = Some(row.len()),
+            }
+
+            colors.extend(row);
+            height += 1;
+        }
+
+        Ok(Program::new((width.unwrap_or(0), height), colors))
+    }
+
+    /// Write the program in the text grid format that `from_grid` reads.
+    pub fn to_grid(&self) -> String {
+        let (width, _) = self.size();
+        let mut grid = String::new();
+
+        for ((x, _), color) in self.codels() {
+            let separator = if x + 1 == width { "\n" } else { " " };
+            write!(grid, "{}{}", to_code(color), separator).unwrap();
+        }
+
+        grid
+    }
+
+    /// Read a program from a PPM image, in either the plain (P3) or the raw
+    /// (P6) format.
+    pub fn from_ppm(bytes: &[u8], options: LoadOptions) -> Result<Program> {
+        let image = image::load_from_memory_with_format(bytes, ImageFormat::Pnm)?;
+
+        Program::from_image(&image, options)
+    }
+}
+
+const LIGHTNESS_CODES: [char; 3] = ['l', 'n', 'd'];
+const HUE_CODES: [char; 6] = ['r', 'y', 'g', 'c', 'b', 'm'];
+
+fn to_code(color: Color) -> String {
+    match color {
+        Color::White => "wh".to_string(),
+        Color::Black => "bk".to_string(),
+        Color::Composite(hue, lightness) => {
+            let mut code = String::new();
+            code.push(LIGHTNESS_CODES[lightness as usize]);
+            code.push(HUE_CODES[hue as usize]);
+            code
+        }
+    }
+}
+
+fn from_code(code: &str) -> Option<Color> {
+    match code {
+        "wh" => return Some(Color::White),
+        "bk" => return Some(Color::Black),
+        _ => {}
+    }
+
+    let mut chars = code.chars();
+    let (lightness, hue) = (chars.next()?, chars.next()?);
+    if chars.next().is_some() {
+        return None;
+    }
+
+    let lightness = LIGHTNESS_CODES.iter().position(|&c| c == lightness)?;
+    let hue = HUE_CODES.iter().position(|&c| c == hue)?;
+
+    Some(Color::Composite(Hue::ALL[hue], Lightness::ALL[lightness]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::{Hue::*, Lightness::*};
+
+    #[test]
+    fn test_program_from_grid() {
+        let source = "; Push 2, and output it.\n\
+                      lr lr nr dm bk\n\
+                      \n\
+                      bk bk dm dm bk ; The end.\n";
+        let program = Program::from_grid(source).unwrap();
+
+        assert_eq!(program.size(), (5, 2));
+        assert_eq!(program.get((2, 0)), Some(Color::Composite(Red, Normal)));
+        assert_eq!(program.get((3, 1)), Some(Color::Composite(Magenta, Dark)));
+        assert_eq!(program.get((4, 1)), Some(Color::Black));
+
+        match Program::from_grid("lr nr\nlr xx") {
+            Err(Error(ErrorKind::Syntax(2, message), _)) => assert!(message.contains("`xx`")),
+            result => panic!("unexpected result: {:?}", result),
+        }
+        match Program::from_grid("lr nr\n\nlr") {
+            Err(Error(ErrorKind::Syntax(3, _), _)) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_program_to_grid() {
+        for program in crate::differential::command_programs() {
+            let grid = program.to_grid();
+            let parsed = Program::from_grid(&grid).unwrap();
+
+            assert_eq!(parsed.size(), program.size());
+            assert!(parsed.codels().eq(program.codels()));
+        }
+
+        let program = Program::new((2, 1), vec![Color::Composite(Cyan, Light), Color::White]);
+        assert_eq!(program.to_grid(), "lc wh\n");
+    }
+
+    #[test]
+    fn test_program_from_ppm() {
+        let plain = b"P3\n# Light red, then black.\n2 1\n255\n255 192 192  0 0 0\n";
+        let raw = b"P6 2 1 255 \xff\xc0\xc0\x00\x00\x00";
+
+        for ppm in [&plain[..], &raw[..]] {
+            let program = Program::from_ppm(ppm, LoadOptions::default()).unwrap();
+
+            assert_eq!(program.size(), (2, 1));
+            assert_eq!(program.get((0, 0)), Some(Color::Composite(Red, Light)));
+            assert_eq!(program.get((1, 0)), Some(Color::Black));
+        }
+
+        assert!(Program::from_ppm(b"P6 2 1 255 \xff", LoadOptions::default()).is_err());
+    }
+}
@@ -0,0 +1,140 @@
+use crate::errors::*;
+use crate::interpreter::{Interpreter, Snapshot};
+use crate::trace::Step;
+
+/// An interpreter that can step backwards as well as forwards.
+///
+/// Every `interval` steps, a snapshot of the interpreter is saved. Going back
+/// restores the closest snapshot before the target step, and replays the
+/// program from there. Execution is deterministic, so replaying always ends up
+/// in the same state, as long as all input was fed to the interpreter before it
+/// started running.
+#[derive(Debug)]
+pub struct History {
+    interpreter: Interpreter,
+    interval: u64,
+    checkpoints: Vec<Snapshot>,
+}
+
+impl History {
+    pub fn new(interpreter: Interpreter, interval: u64) -> Self {
+        let checkpoints = vec![interpreter.snapshot()];
+
+        History {
+            interpreter,
+            interval: interval.max(1),
+            checkpoints,
+        }
+    }
+
+    pub fn interpreter(&self) -> &Interpreter {
+        &self.interpreter
+    }
+
+    pub fn into_interpreter(self) -> Interpreter {
+        self.interpreter
+    }
+
+    /// Step forward, saving a checkpoint if one is due.
+    pub fn step(&mut self) -> Option<Result<Step>> {
+        let result = self.interpreter.step()?;
+        let steps = self.interpreter.steps();
+
+        let latest = self.checkpoints.last().map_or(0, Snapshot::steps);
+        if steps.is_multiple_of(self.interval) && steps > latest {
+            self.checkpoints.push(self.interpreter.snapshot());
+        }
+
+        Some(result)
+    }
+
+    /// Go back one step.
+    ///
+    /// # Returns
+    ///
+    /// `false` if the interpreter is already at the start of the program.
+    pub fn step_back(&mut self) -> Result<bool> {
+        match self.interpreter.steps() {
+            0 => Ok(false),
+            steps => self.seek(steps - 1).map(|_| true),
+        }
+    }
+
+    /// Go to the state after a number of steps, either forwards or backwards.
+    ///
+    /// If the program halts before reaching that step, this stops at the
+    /// halting state.
+    pub fn seek(&mut self, target: u64) -> Result<()> {
+        if target < self.interpreter.steps() {
+            let index = self
+                .checkpoints
+                .iter()
+                .rposition(|checkpoint| checkpoint.steps() <= target)
+                .unwrap(); // The first checkpoint is at step 0.
+
+            self.interpreter.restore(&self.checkpoints[index]);
+        }
+
+        while self.interpreter.steps() < target {
+            match self.step() {
+                Some(result) => {
+                    result?;
+                }
+                None => break,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::{Color, Hue::*, Lightness::*};
+    use crate::program::Program;
+
+    /// Push 1, then keep alternating between pushing 1 and popping.
+    fn looping_program() -> Program {
+        let lr = Color::Composite(Red, Light);
+        let nr = Color::Composite(Red, Normal);
+
+        Program::new((2, 1), vec![lr, nr])
+    }
+
+    #[test]
+    fn test_history_step_back() {
+        let mut history = History::new(Interpreter::new(looping_program()), 3);
+
+        for _ in 0..10 {
+            history.step().unwrap().unwrap();
+        }
+        let stack = history.interpreter().stack().clone();
+
+        history.step().unwrap().unwrap();
+        assert!(history.step_back().unwrap());
+        assert_eq!(history.interpreter().steps(), 10);
+        assert_eq!(history.interpreter().stack(), &stack);
+    }
+
+    #[test]
+    fn test_history_seek() {
+        let mut reference = Interpreter::new(looping_program());
+        let mut history = History::new(Interpreter::new(looping_program()), 4);
+
+        history.seek(20).unwrap();
+
+        for target in (0..=20).rev() {
+            history.seek(target).unwrap();
+            assert_eq!(history.interpreter().steps(), target);
+
+            reference.restore(&Interpreter::new(looping_program()).snapshot());
+            for _ in 0..target {
+                reference.step().unwrap().unwrap();
+            }
+            assert_eq!(history.interpreter().snapshot(), reference.snapshot());
+        }
+
+        assert!(!history.step_back().unwrap());
+    }
+}
@@ -1,13 +1,19 @@
-use std::collections::HashSet;
+use std::fmt;
 
+use crate::blocks::{Block, BlockId, Blocks};
 use crate::command::Command;
+use crate::dialect::Dialect;
 use crate::errors::*;
+use crate::machine::Machine;
 use crate::program::Program;
 use crate::stack::Stack;
+use crate::trace::Step;
 use crate::util::Coords;
 
-#[derive(Debug, Copy, Clone, PartialEq)]
-enum DirectionPointer {
+/// The direction pointer, which decides in which direction the interpreter
+/// leaves a color block.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum DirectionPointer {
     Up,
     Right,
     Down,
@@ -36,10 +42,42 @@ impl DirectionPointer {
             Right => Up,
         }
     }
+
+    /// Move one codel in this direction, if that doesn't underflow.
+    pub fn offset(self, coords: Coords) -> Option<Coords> {
+        use self::DirectionPointer::*;
+
+        let (x, y) = coords;
+
+        match self {
+            Up => y.checked_sub(1).map(|y| (x, y)),
+            Right => Some((x + 1, y)),
+            Down => Some((x, y + 1)),
+            Left => x.checked_sub(1).map(|x| (x, y)),
+        }
+    }
+}
+
+impl fmt::Display for DirectionPointer {
+    /// Write the direction in lowercase, like "up".
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::DirectionPointer::*;
+
+        let name = match self {
+            Up => "up",
+            Right => "right",
+            Down => "down",
+            Left => "left",
+        };
+
+        f.write_str(name)
+    }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq)]
-enum CodelChooser {
+/// The codel chooser, which decides which codel on the edge of a color block
+/// the interpreter leaves from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum CodelChooser {
     Left,
     Right,
 }
@@ -53,212 +91,541 @@ impl CodelChooser {
     }
 }
 
-#[derive(Debug)]
+impl fmt::Display for CodelChooser {
+    /// Write the side in lowercase, like "left".
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            CodelChooser::Left => "left",
+            CodelChooser::Right => "right",
+        };
+
+        f.write_str(name)
+    }
+}
+
+/// A copy of all the state of an `Interpreter` that changes while it runs.
+///
+/// The program itself never changes, and input is only ever appended to, so
+/// restoring a snapshot only needs to know how far along both were.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    dp: DirectionPointer,
+    cc: CodelChooser,
+    stack: Stack,
+    position: Coords,
+    input_cursor: usize,
+    output_len: usize,
+    steps: u64,
+    halted: bool,
+}
+
+impl Snapshot {
+    /// The number of steps the interpreter had taken when this snapshot was
+    /// made.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+}
+
+/// What's needed to notice that the interpreter is back in a state it was in
+/// before: a snapshot, which is taken again whenever `period` steps have
+/// passed since the last one, doubling `period` each time.
+///
+/// Once the interpreter is in a loop, and the period is longer than the
+/// loop, a snapshot is taken in the loop and the loop returns to it. This
+/// only ever needs one snapshot, and finds the exact length of the loop.
+#[derive(Debug, Clone)]
+struct LoopDetector {
+    saved: Snapshot,
+    period: u64,
+}
+
+#[derive(Debug, Clone)]
 pub struct Interpreter {
     program: Program,
+    blocks: Blocks,
     dp: DirectionPointer,
     cc: CodelChooser,
-    stack: Stack,
+    machine: Machine,
     position: Coords,
-    last_block: u64,
+    steps: u64,
+    halted: bool,
+    loop_detector: Option<LoopDetector>,
 }
 
 impl Interpreter {
     pub fn new(program: Program) -> Self {
         Interpreter {
+            blocks: Blocks::new(&program),
             program,
             dp: DirectionPointer::Right,
             cc: CodelChooser::Left,
-            stack: Stack::new(),
+            machine: Machine::new(),
             position: (0, 0),
-            last_block: 0,
+            steps: 0,
+            halted: false,
+            loop_detector: None,
         }
     }
 
-    pub fn run(&mut self) -> Result<()> {
-        while let Some(result) = self.step() {
-            result?;
+    /// Choose whether to check for infinite loops while running.
+    ///
+    /// A loop is found when the interpreter gets back to exactly the same
+    /// position, DP, CC and stack, without reading or writing anything in
+    /// between. The program could never do anything else, so the step that
+    /// completes the loop fails with `ErrorKind::InfiniteLoop`, and the
+    /// interpreter halts. Loops that wait for input that hasn't been fed yet
+    /// count too.
+    pub fn set_loop_detection(&mut self, enabled: bool) {
+        self.loop_detector = if enabled {
+            Some(LoopDetector {
+                saved: self.snapshot(),
+                period: 1,
+            })
+        } else {
+            None
+        };
+    }
+
+    /// Append text to the input that `IntIn` and `CharIn` read from.
+    pub fn feed_input(&mut self, input: &str) {
+        self.machine.feed_input(input);
+    }
+
+    /// Whether the next step reads input, but there's none left that it could
+    /// read, so more should be fed first.
+    pub fn needs_input(&self) -> bool {
+        if self.halted {
+            return false;
         }
 
-        Ok(())
+        match self
+            .blocks
+            .step(self.position, self.dp, self.cc, &self.dialect())
+        {
+            Ok(Some(step)) => step
+                .command
+                .is_some_and(|command| self.machine.needs_input(command)),
+            _ => false,
+        }
+    }
+
+    /// Choose how to handle the cases that the specification is ambiguous
+    /// about.
+    pub fn set_dialect(&mut self, dialect: Dialect) {
+        self.machine.set_dialect(dialect);
     }
 
-    pub fn step(&mut self) -> Option<Result<()>> {
-        unimplemented!()
+    pub fn dialect(&self) -> Dialect {
+        self.machine.dialect()
     }
 
-    fn current_block(&self) -> HashSet<Coords> {
-        self.program.color_block(self.position)
+    pub fn program(&self) -> &Program {
+        &self.program
     }
 
-    fn next_coords(&self) -> Coords {
-        use self::CodelChooser as CC;
-        use self::DirectionPointer as DP;
+    pub fn dp(&self) -> DirectionPointer {
+        self.dp
+    }
+
+    pub fn cc(&self) -> CodelChooser {
+        self.cc
+    }
+
+    pub fn stack(&self) -> &Stack {
+        self.machine.stack()
+    }
+
+    pub fn position(&self) -> Coords {
+        self.position
+    }
 
-        fn x_key(&(x, _): &Coords) -> usize {
-            x
+    /// Everything the program has written so far.
+    pub fn output(&self) -> &str {
+        self.machine.output()
+    }
+
+    /// The number of steps taken so far. A step is one move from a color
+    /// block into the next one, including any sliding through white.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Save the current state, so it can be returned to later with `restore`.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            dp: self.dp,
+            cc: self.cc,
+            stack: self.machine.stack().clone(),
+            position: self.position,
+            input_cursor: self.machine.input_cursor(),
+            output_len: self.machine.output().len(),
+            steps: self.steps,
+            halted: self.halted,
         }
-        fn y_key(&(_, y): &Coords) -> usize {
-            y
+    }
+
+    /// Return to the state saved in a snapshot.
+    ///
+    /// The snapshot should come from this interpreter, or from one running the
+    /// same program with the same input.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.dp = snapshot.dp;
+        self.cc = snapshot.cc;
+        self.machine
+            .rewind(&snapshot.stack, snapshot.input_cursor, snapshot.output_len);
+        self.position = snapshot.position;
+        self.steps = snapshot.steps;
+        self.halted = snapshot.halted;
+
+        // The saved snapshot might be from after this one.
+        if self.loop_detector.is_some() {
+            self.set_loop_detection(true);
         }
+    }
 
-        let current_block = self.current_block();
+    pub fn run(&mut self) -> Result<()> {
+        while let Some(result) = self.step() {
+            result?;
+        }
 
-        let edge = if self.dp == DP::Up || self.dp == DP::Down {
-            let iter = current_block.iter().map(|&c| c);
+        Ok(())
+    }
 
-            let (_, farthest_y) = match self.dp {
-                DP::Up => iter.min_by_key(y_key),
-                DP::Down => iter.max_by_key(y_key),
-                _ => unreachable!(),
+    /// Move to the next color block, and execute the command that the
+    /// transition encodes.
+    ///
+    /// # Returns
+    ///
+    /// A description of the move, or `None` once the program has halted.
+    pub fn step(&mut self) -> Option<Result<Step>> {
+        if self.halted {
+            return None;
+        }
+
+        let dialect = self.dialect();
+        let mut step = match self.blocks.step(self.position, self.dp, self.cc, &dialect) {
+            Ok(Some(step)) => step,
+            Ok(None) => return self.halt(),
+            Err(error) => {
+                self.halted = true;
+                return Some(Err(error));
             }
-            .unwrap(); // We can unwrap here because the current block is never empty.
-
-            current_block
-                .iter()
-                .filter(|&&(_x, y)| y == farthest_y)
-                .map(|&y| y)
-                .collect::<Vec<_>>()
-        } else if self.dp == DP::Left || self.dp == DP::Right {
-            let iter = current_block.iter().map(|&c| c);
-
-            let (farthest_x, _) = match self.dp {
-                DP::Left => iter.min_by_key(x_key),
-                DP::Right => iter.max_by_key(x_key),
-                _ => unreachable!(),
+        };
+
+        let block_size = self.current_block().size();
+        self.position = step.to;
+        self.dp = step.next_dp;
+        self.cc = step.next_cc;
+        self.steps += 1;
+
+        if let Some(command) = step.command {
+            if let Err(error) = self.execute_command(command, block_size) {
+                self.halted = true;
+                return Some(Err(error));
             }
-            .unwrap(); // Same as above.
+        }
 
-            current_block
-                .iter()
-                .filter(|&&(x, _y)| x == farthest_x)
-                .map(|&x| x)
-                .collect::<Vec<_>>()
-        } else {
-            unreachable!()
+        step.next_dp = self.dp;
+        step.next_cc = self.cc;
+
+        if let Some(length) = self.find_loop() {
+            self.halted = true;
+            let blocks = self.loop_blocks(length);
+
+            return Some(Err(ErrorKind::InfiniteLoop(length, blocks).into()));
+        }
+
+        Some(Ok(step))
+    }
+
+    /// The length of the loop the interpreter just completed, if loop
+    /// detection is enabled.
+    fn find_loop(&mut self) -> Option<u64> {
+        let detector = self.loop_detector.as_ref()?;
+        let saved = &detector.saved;
+
+        // Input is only ever appended to, and output only grows, so if they're
+        // the same length nothing was read or written.
+        let repeated = saved.position == self.position
+            && saved.dp == self.dp
+            && saved.cc == self.cc
+            && saved.input_cursor == self.machine.input_cursor()
+            && saved.output_len == self.machine.output().len()
+            && &saved.stack == self.machine.stack();
+
+        if repeated {
+            return Some(self.steps - saved.steps);
         }
-        .into_iter();
-
-        match self.dp {
-            DP::Left => match self.cc {
-                CC::Right => edge.max_by_key(y_key),
-                CC::Left => edge.min_by_key(y_key),
-            },
-            DP::Right => match self.cc {
-                CC::Right => edge.min_by_key(y_key),
-                CC::Left => edge.max_by_key(y_key),
-            },
-            DP::Up => match self.cc {
-                CC::Right => edge.max_by_key(x_key),
-                CC::Left => edge.min_by_key(x_key),
-            },
-            DP::Down => match self.cc {
-                CC::Right => edge.min_by_key(x_key),
-                CC::Left => edge.max_by_key(x_key),
-            },
+
+        if self.steps - saved.steps >= detector.period {
+            let period = detector.period * 2;
+            self.loop_detector = Some(LoopDetector {
+                saved: self.snapshot(),
+                period,
+            });
         }
-        .unwrap() // See above.
+
+        None
     }
 
-    fn execute_command(&mut self, command: &Command) {
-        match *command {
-            Command::Push => {
-                unimplemented!();
-            }
+    /// Every block that a loop of `length` steps from here passes through.
+    fn loop_blocks(&self, length: u64) -> Vec<BlockId> {
+        let mut interpreter = self.clone();
+        interpreter.halted = false;
+        interpreter.loop_detector = None;
 
-            Command::Pop => {
-                self.stack.pop();
-            }
+        let mut blocks = Vec::new();
+        for _ in 0..length {
+            blocks.extend(self.blocks.id_at(interpreter.position));
+            interpreter.step();
+        }
 
-            Command::Add => {
-                self.stack.fold_top(|b, a| a + b);
-            }
+        blocks.sort_unstable();
+        blocks.dedup();
 
-            Command::Subtract => {
-                self.stack.fold_top(|b, a| a * b);
-            }
+        blocks
+    }
 
-            Command::Multiply => {
-                self.stack.fold_top(|b, a| a - b);
-            }
+    fn halt(&mut self) -> Option<Result<Step>> {
+        self.halted = true;
 
-            Command::Divide => {
-                self.stack.fold_top(|b, a| a / b);
-            }
+        None
+    }
 
-            Command::Mod => {
-                self.stack.fold_top(|b, a| a.rem_euclid(b));
-            }
+    fn current_block(&self) -> &Block {
+        let id = self.blocks.id_at(self.position).unwrap();
+        // The position is always on the image, so it's always in a block.
 
-            Command::Not => {
-                if let Some(value) = self.stack.pop() {
-                    self.stack.push(if value != 0 { 0 } else { 1 });
-                }
-            }
+        &self.blocks[id]
+    }
 
-            Command::Greater => {
-                self.stack.fold_top(|b, a| if a > b { 1 } else { 0 });
-            }
+    /// Execute a command, and turn the DP or switch the CC if it says so.
+    fn execute_command(&mut self, command: Command, block_size: usize) -> Result<()> {
+        let turns = self.machine.try_execute(command, block_size)?;
 
+        match command {
             Command::Pointer => {
-                if let Some(value) = self.stack.pop() {
-                    self.pointer(value);
+                for _ in 0..turns {
+                    self.dp.rotate_clockwise();
                 }
             }
+            Command::Switch if turns == 1 => self.cc.switch(),
+            _ => {}
+        }
 
-            Command::Switch => {
-                if let Some(value) = self.stack.pop() {
-                    self.switch(value);
-                }
-            }
+        Ok(())
+    }
+}
 
-            Command::Duplicate => {
-                if let Some(&value) = self.stack.last() {
-                    self.stack.push(value);
-                }
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::{Color, Hue::*, Lightness::*};
+
+    const B: Color = Color::Black;
+    const W: Color = Color::White;
+    const LR: Color = Color::Composite(Red, Light);
+    const NR: Color = Color::Composite(Red, Normal);
+    const DR: Color = Color::Composite(Red, Dark);
+    const LY: Color = Color::Composite(Yellow, Light);
+    const DM: Color = Color::Composite(Magenta, Dark);
+
+    /// Push 3, push 2, subtract, and output the result as a number.
+    ///
+    /// The last block is shaped so that every exit is blocked, which halts the
+    /// program.
+    #[rustfmt::skip]
+    fn subtract_program() -> Program {
+        Program::new((9, 2), vec![
+            LR, LR, LR, NR, NR, DR, LY, NR, B,
+            B,  B,  B,  B,  B,  B,  NR, NR, B,
+        ])
+    }
 
-            Command::Roll => {
-                if let (Some(times), Some(depth)) = self.stack.pop2() {
-                    self.stack.roll(depth as usize, times);
-                }
-            }
+    #[test]
+    fn test_direction_pointer_offset() {
+        use super::DirectionPointer::*;
 
-            Command::IntIn => {
-                unimplemented!();
-            }
+        assert_eq!(Right.offset((1, 1)), Some((2, 1)));
+        assert_eq!(Down.offset((1, 1)), Some((1, 2)));
+        assert_eq!(Left.offset((0, 1)), None);
+        assert_eq!(Up.offset((1, 0)), None);
+    }
 
-            Command::CharIn => {
-                unimplemented!();
-            }
+    #[test]
+    #[rustfmt::skip]
+    fn test_interpreter_run() {
+        let mut interpreter = Interpreter::new(subtract_program());
+        interpreter.run().unwrap();
+
+        assert!(interpreter.is_halted());
+        assert_eq!(interpreter.steps(), 4);
+        assert_eq!(interpreter.output(), "1");
+        assert_eq!(interpreter.stack(), &Stack::new());
+
+        // Push 2, output as number.
+        let program = Program::new((5, 2), vec![
+            LR, LR, NR, DM, B,
+            B,  B,  DM, DM, B,
+        ]);
+        let mut interpreter = Interpreter::new(program);
+        interpreter.run().unwrap();
+
+        assert_eq!(interpreter.output(), "2");
+        assert!(interpreter.step().is_none());
+    }
 
-            Command::IntOut => {
-                unimplemented!();
-            }
+    #[test]
+    fn test_interpreter_needs_input() {
+        let program = crate::asm::assemble("push 1\nin char\nout char").unwrap();
+        let mut interpreter = Interpreter::new(program);
 
-            Command::CharOut => {
-                unimplemented!();
-            }
+        // It stops right before reading, after the push.
+        assert!(!interpreter.needs_input());
+        while !interpreter.needs_input() {
+            interpreter.step().unwrap().unwrap();
+        }
+        assert_eq!(interpreter.stack().to_vec(), vec![1]);
 
-            Command::NoOp => return,
+        interpreter.feed_input("x");
+        assert!(!interpreter.needs_input());
+        interpreter.run().unwrap();
+        assert_eq!(interpreter.output(), "x");
+        assert!(!interpreter.needs_input());
+    }
+
+    #[test]
+    fn test_interpreter_slides_through_white() {
+        let program = Program::new((4, 1), vec![LR, W, W, NR]);
+        let mut interpreter = Interpreter::new(program);
+
+        interpreter.step().unwrap().unwrap();
+        assert_eq!(interpreter.position(), (3, 0));
+        // No command is executed when passing through white.
+        assert_eq!(interpreter.stack(), &Stack::new());
+    }
+
+    #[test]
+    fn test_interpreter_dialect() {
+        use crate::dialect::{Dialect, StartOnWhite};
+
+        let mut interpreter = Interpreter::new(subtract_program());
+        interpreter.set_dialect(Dialect::STRICT);
+        interpreter.run().unwrap();
+        assert_eq!(interpreter.output(), "1");
+
+        // Where the first step goes, or `None` if the program halts first.
+        let first_step = |program, dialect| {
+            let mut interpreter = Interpreter::new(program);
+            interpreter.set_dialect(dialect);
+            interpreter
+                .step()
+                .map(|step| step.map(|_| interpreter.position()))
+        };
+
+        let program = Program::new((2, 1), vec![W, LR]);
+        let halt = Dialect {
+            start_on_white: StartOnWhite::Halt,
+            ..Dialect::NPIET
+        };
+        assert_eq!(
+            first_step(program.clone(), Dialect::NPIET)
+                .unwrap()
+                .unwrap(),
+            (1, 0)
+        );
+        assert!(first_step(program.clone(), halt).is_none());
+        match first_step(program, Dialect::STRICT) {
+            Some(Err(Error(ErrorKind::StartOnWhite, _))) => {}
+            result => panic!("unexpected result: {:?}", result),
         }
+
+        // Sliding right is blocked by the edge, so it turns down.
+        #[rustfmt::skip]
+        let program = Program::new((2, 2), vec![
+            W,  W,
+            B,  NR,
+        ]);
+        let limited = Dialect {
+            white_retry_limit: Some(0),
+            start_on_white: StartOnWhite::Slide,
+            ..Dialect::STRICT
+        };
+        assert_eq!(
+            first_step(program.clone(), Dialect::NPIET)
+                .unwrap()
+                .unwrap(),
+            (1, 1)
+        );
+        assert!(first_step(program, limited).is_none());
+    }
+
+    #[test]
+    fn test_interpreter_snapshot_restore() {
+        let mut interpreter = Interpreter::new(subtract_program());
+
+        interpreter.step().unwrap().unwrap();
+        let snapshot = interpreter.snapshot();
+
+        interpreter.run().unwrap();
+        assert_eq!(interpreter.output(), "1");
+
+        interpreter.restore(&snapshot);
+        assert_eq!(interpreter.steps(), 1);
+        assert_eq!(interpreter.position(), (3, 0));
+        assert_eq!(interpreter.stack(), &Stack::from_vec(vec![3]));
+        assert_eq!(interpreter.output(), "");
+        assert!(!interpreter.is_halted());
+
+        interpreter.run().unwrap();
+        assert_eq!(interpreter.output(), "1");
     }
 
-    fn pointer(&mut self, steps: i64) {
-        if steps >= 0 {
-            for _ in 0..steps {
-                self.dp.rotate_clockwise();
+    #[test]
+    fn test_interpreter_loop_detection() {
+        use crate::asm::assemble;
+
+        let run = |source, detect| {
+            let mut interpreter = Interpreter::new(assemble(source).unwrap());
+            interpreter.set_loop_detection(detect);
+
+            for _ in 0..1_000 {
+                match interpreter.step() {
+                    Some(Ok(_)) => {}
+                    Some(Err(error)) => return Some(error),
+                    None => break,
+                }
             }
-        } else {
-            for _ in 0..steps.abs() {
-                self.dp.rotate_counterclockwise();
+
+            None
+        };
+
+        // The first row is `lr wh wh wh wh lr nr lr`, and a path of white
+        // leads from below the last codel back to the white ones. Pushing,
+        // popping and sliding back are one step each.
+        let error = run("loop:\npush 1\npop\njmp loop", true).unwrap();
+        let blocks = Blocks::new(&assemble("loop:\npush 1\npop\njmp loop").unwrap());
+        let looped = [(5, 0), (6, 0), (7, 0)]
+            .iter()
+            .map(|&coords| blocks.id_at(coords).unwrap())
+            .collect::<Vec<_>>();
+        match error.kind() {
+            ErrorKind::InfiniteLoop(length, blocks) => {
+                assert_eq!(*length, 3);
+                assert_eq!(blocks, &looped);
             }
+            _ => panic!("unexpected error: {}", error),
         }
-    }
 
-    fn switch(&mut self, times: i64) {
-        for _ in 0..times {
-            self.cc.switch();
-        }
+        assert!(run("loop:\npush 1\npop\njmp loop", false).is_none());
+        // Neither of these ever gets back to the same state.
+        assert!(run("loop:\npush 1\nout num\njmp loop", true).is_none());
+        assert!(run("loop:\npush 1\njmp loop", true).is_none());
+        assert!(run("push 1\nout num", true).is_none());
     }
 }
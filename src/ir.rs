@@ -0,0 +1,496 @@
+use crate::cfg::{Cfg, State};
+use crate::command::Command;
+use crate::dialect::Dialect;
+use crate::machine::Machine;
+use crate::stack::Stack;
+
+/// The position of a node in `Ir::nodes`.
+pub type NodeId = usize;
+
+/// Something a node does to the machine, without changing where it goes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Op {
+    /// Push a value. Unlike `Command::Push`, this can be any value.
+    Push(i64),
+    /// Execute a command. This is never `Push`, `NoOp`, `Pointer` or `Switch`.
+    Execute(Command),
+}
+
+/// Where a node goes after its operations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Next {
+    Halt,
+    Jump(NodeId),
+    /// Execute `Pointer` or `Switch`, and go to the node for its result.
+    Branch(Command, Vec<NodeId>),
+}
+
+/// A straight line of operations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Node {
+    state: State,
+    ops: Vec<Op>,
+    next: Next,
+}
+
+impl Node {
+    /// The state in the control flow graph that the node starts at.
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    pub fn ops(&self) -> &[Op] {
+        &self.ops
+    }
+
+    pub fn next(&self) -> &Next {
+        &self.next
+    }
+
+    /// Work out whatever can be worked out ahead of time, including the
+    /// branch at the end.
+    fn fold(&mut self, dialect: &Dialect) {
+        let mut ops = Vec::with_capacity(self.ops.len());
+
+        for &op in &self.ops {
+            match op {
+                Op::Push(_) => ops.push(op),
+                Op::Execute(command) => fold_command(&mut ops, command, dialect),
+            }
+        }
+
+        self.ops = ops;
+
+        if let Next::Branch(command, targets) = &self.next {
+            let (command, targets) = (*command, targets.clone());
+
+            if let Some(&Op::Push(value)) = self.ops.last() {
+                self.ops.pop();
+
+                let turns = Machine::with_stack(Stack::from_vec(vec![value])).execute(command, 1);
+                self.next = Next::Jump(targets[turns as usize]);
+            } else if targets.iter().all(|&target| target == targets[0]) {
+                // Either way, the value is popped.
+                self.ops.push(Op::Execute(Command::Pop));
+                self.next = Next::Jump(targets[0]);
+            }
+        }
+    }
+}
+
+/// An intermediate representation of a program, for backends that don't need
+/// to know about the image it came from.
+///
+/// It starts with one node for every state of the control flow graph, and a
+/// program starts at the first node. `optimize` can then rewrite it into
+/// fewer, longer nodes. Either way, running it has the same effect on a
+/// `Machine` as running the program, but the steps aren't counted the same
+/// way. Commands are only worked out ahead of time the way the dialect of the
+/// control flow graph executes them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ir {
+    nodes: Vec<Node>,
+    dialect: Dialect,
+}
+
+impl Ir {
+    pub fn new(cfg: &Cfg) -> Self {
+        let states = cfg.states();
+        let target = |state| cfg.index(state).unwrap(); // Every target is reachable.
+
+        let mut nodes = states
+            .iter()
+            .map(|&state| {
+                let edges = cfg.edges(state);
+                let mut node = Node {
+                    state,
+                    ops: Vec::new(),
+                    next: Next::Halt,
+                };
+
+                match (edges.first(), edges.len()) {
+                    (None, _) => {}
+                    (Some(edge), 1) => {
+                        match edge.command {
+                            Some(Command::NoOp) | None => {}
+                            Some(Command::Push) => {
+                                let size = cfg.blocks()[state.0].size();
+                                node.ops.push(Op::Push(size as i64));
+                            }
+                            Some(command) => node.ops.push(Op::Execute(command)),
+                        }
+
+                        node.next = Next::Jump(target(edge.to));
+                    }
+                    (Some(edge), _) => {
+                        let mut targets = vec![0; edges.len()];
+                        for edge in edges {
+                            targets[edge.branch.unwrap_or(0) as usize] = target(edge.to);
+                        }
+
+                        // Only commands can branch.
+                        node.next = Next::Branch(edge.command.unwrap(), targets);
+                    }
+                }
+
+                node
+            })
+            .collect::<Vec<_>>();
+
+        // An empty program halts straight away.
+        if nodes.is_empty() {
+            nodes.push(Node {
+                state: cfg.entry(),
+                ops: Vec::new(),
+                next: Next::Halt,
+            });
+        }
+
+        Ir {
+            nodes,
+            dialect: *cfg.dialect(),
+        }
+    }
+
+    /// Every node. The program starts at the first one.
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    /// Rewrite the program so it does less work, until nothing else can be
+    /// improved:
+    ///
+    /// - Commands that only depend on values pushed right before them are
+    ///   worked out ahead of time. That folds chains of arithmetic into one
+    ///   push, and removes values that are pushed and then popped.
+    /// - `Pointer` and `Switch` on a known value become jumps.
+    /// - Jumps to nodes that do nothing, like slides across white, go straight
+    ///   to where those nodes go.
+    /// - Nodes that are only ever reached from one other node are merged into
+    ///   it, so more can be folded.
+    /// - Nodes that can't be reached anymore are removed.
+    pub fn optimize(&mut self) {
+        loop {
+            let before = self.clone();
+
+            for node in &mut self.nodes {
+                node.fold(&self.dialect);
+            }
+            self.thread_jumps();
+            self.merge();
+            self.prune();
+
+            if *self == before {
+                break;
+            }
+        }
+    }
+
+    /// Where a jump to a node really ends up, skipping nodes that do nothing.
+    fn forward(&self, mut id: NodeId) -> NodeId {
+        let mut seen = vec![false; self.nodes.len()];
+
+        while let (true, &Next::Jump(next)) = (self.nodes[id].ops.is_empty(), &self.nodes[id].next)
+        {
+            // A loop that does nothing has to stay somewhere.
+            if seen[next] {
+                break;
+            }
+
+            seen[id] = true;
+            id = next;
+        }
+
+        id
+    }
+
+    fn thread_jumps(&mut self) {
+        let forwarded = (0..self.nodes.len())
+            .map(|id| self.forward(id))
+            .collect::<Vec<_>>();
+
+        for node in &mut self.nodes {
+            match &mut node.next {
+                Next::Halt => {}
+                Next::Jump(next) => *next = forwarded[*next],
+                Next::Branch(_, targets) => {
+                    for target in targets {
+                        *target = forwarded[*target];
+                    }
+                }
+            }
+        }
+    }
+
+    /// How many jumps go to each node, counting the start as one.
+    fn references(&self) -> Vec<usize> {
+        let mut references = vec![0; self.nodes.len()];
+        references[0] += 1;
+
+        for node in &self.nodes {
+            match &node.next {
+                Next::Halt => {}
+                Next::Jump(next) => references[*next] += 1,
+                Next::Branch(_, targets) => {
+                    for &target in targets {
+                        references[target] += 1;
+                    }
+                }
+            }
+        }
+
+        references
+    }
+
+    fn merge(&mut self) {
+        let references = self.references();
+
+        for id in 0..self.nodes.len() {
+            while let Next::Jump(next) = self.nodes[id].next {
+                if next == id || references[next] != 1 {
+                    break;
+                }
+
+                // Nothing else jumps to the merged node, so it's left for
+                // `prune` to remove.
+                let ops = std::mem::take(&mut self.nodes[next].ops);
+                let following = std::mem::replace(&mut self.nodes[next].next, Next::Halt);
+
+                let node = &mut self.nodes[id];
+                node.ops.extend(ops);
+                node.next = following;
+            }
+        }
+    }
+
+    /// Remove nodes that can't be reached, keeping the rest in order.
+    fn prune(&mut self) {
+        let mut reachable = vec![false; self.nodes.len()];
+        let mut pending = vec![0];
+        reachable[0] = true;
+
+        while let Some(id) = pending.pop() {
+            let targets = match &self.nodes[id].next {
+                Next::Halt => &[][..],
+                Next::Jump(next) => std::slice::from_ref(next),
+                Next::Branch(_, targets) => &targets[..],
+            };
+
+            for &target in targets {
+                if !reachable[target] {
+                    reachable[target] = true;
+                    pending.push(target);
+                }
+            }
+        }
+
+        let mut ids = vec![0; self.nodes.len()];
+        let mut count = 0;
+        for (id, &reachable) in reachable.iter().enumerate() {
+            ids[id] = count;
+            count += reachable as usize;
+        }
+
+        let mut index = 0;
+        self.nodes.retain(|_| {
+            index += 1;
+            reachable[index - 1]
+        });
+
+        for node in &mut self.nodes {
+            match &mut node.next {
+                Next::Halt => {}
+                Next::Jump(next) => *next = ids[*next],
+                Next::Branch(_, targets) => {
+                    for target in targets {
+                        *target = ids[*target];
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Add a command to the end of `ops`, working it out ahead of time if it only
+/// depends on values that were just pushed.
+fn fold_command(ops: &mut Vec<Op>, command: Command, dialect: &Dialect) {
+    let constants = ops
+        .iter()
+        .rev()
+        .take_while(|op| matches!(op, Op::Push(_)))
+        .count();
+    let values = ops[ops.len() - constants..]
+        .iter()
+        .map(|op| match op {
+            Op::Push(value) => *value,
+            Op::Execute(_) => unreachable!(),
+        })
+        .collect::<Vec<_>>();
+
+    let used = match operands(command, &values) {
+        Some(used) => used,
+        None => {
+            ops.push(Op::Execute(command));
+            return;
+        }
+    };
+
+    let mut machine = Machine::with_stack(Stack::from_vec(values[constants - used..].to_vec()));
+    machine.set_dialect(*dialect);
+
+    // Commands that stop with an error have to be left for when it runs.
+    if machine.try_execute(command, 1).is_err() {
+        ops.push(Op::Execute(command));
+        return;
+    }
+
+    ops.truncate(ops.len() - used);
+    ops.extend(machine.stack().iter().map(|&value| Op::Push(value)));
+}
+
+/// How many of the values on top of the stack a command depends on, if they
+/// are all known. `values` are the known ones, with the top last.
+fn operands(command: Command, values: &[i64]) -> Option<usize> {
+    let used = match command {
+        Command::Pop | Command::Duplicate | Command::Not => 1,
+        Command::Add
+        | Command::Subtract
+        | Command::Multiply
+        | Command::Divide
+        | Command::Mod
+        | Command::Greater => 2,
+        Command::Roll => match values {
+            // A roll that's ignored doesn't depend on anything deeper.
+            [.., depth, _] if *depth <= 0 => 2,
+            [.., depth, _] if *depth <= values.len() as i64 - 2 => *depth as usize + 2,
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    Some(used).filter(|&used| used <= values.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+    use crate::differential::{self, INPUT};
+    use crate::interpreter::Interpreter;
+    use crate::program::Program;
+
+    /// Run the IR to the end, and return the machine.
+    fn run(ir: &Ir, input: &str) -> Machine {
+        let mut machine = Machine::new();
+        machine.feed_input(input);
+
+        let mut id = 0;
+        for _ in 0..100_000 {
+            let node = &ir.nodes()[id];
+
+            for &op in node.ops() {
+                match op {
+                    Op::Push(value) => machine.push(value),
+                    Op::Execute(command) => {
+                        machine.execute(command, 1);
+                    }
+                }
+            }
+
+            id = match node.next() {
+                Next::Halt => return machine,
+                Next::Jump(next) => *next,
+                Next::Branch(command, targets) => targets[machine.execute(*command, 1) as usize],
+            };
+        }
+
+        panic!("the IR didn't halt");
+    }
+
+    fn assert_same_behavior(program: &Program, input: &str) {
+        let mut interpreter = Interpreter::new(program.clone());
+        interpreter.feed_input(input);
+        while let Some(result) = interpreter.step() {
+            result.unwrap();
+        }
+
+        let mut ir = Ir::new(&Cfg::new(program));
+        let machine = run(&ir, input);
+        assert_eq!(machine.output(), interpreter.output());
+        assert_eq!(machine.stack(), interpreter.stack());
+
+        ir.optimize();
+        let machine = run(&ir, input);
+        assert_eq!(machine.output(), interpreter.output());
+        assert_eq!(machine.stack(), interpreter.stack());
+    }
+
+    #[test]
+    fn test_ir_optimize() {
+        for program in &differential::command_programs() {
+            assert_same_behavior(program, INPUT);
+            assert_same_behavior(program, "");
+        }
+
+        for program in &differential::random_programs(100) {
+            assert_same_behavior(program, INPUT);
+        }
+
+        let ir = Ir::new(&Cfg::new(&Program::new((0, 0), vec![])));
+        assert_eq!(ir.nodes().len(), 1);
+        assert_eq!(ir.nodes()[0].next(), &Next::Halt);
+    }
+
+    #[test]
+    fn test_ir_folding() {
+        let optimized = |source| {
+            let mut ir = Ir::new(&Cfg::new(&assemble(source).unwrap()));
+            ir.optimize();
+            ir.nodes().to_vec()
+        };
+
+        let nodes = optimized("push 2\npush 3\nadd\npush 4\nmul\nout num");
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(nodes[0].ops(), [Op::Push(20), Op::Execute(Command::IntOut)]);
+        assert_eq!(nodes[0].next(), &Next::Halt);
+
+        // The branch is always taken.
+        let nodes = optimized("push 1\njnz a\npush 9\nout num\na:\npush 7\nout num");
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(nodes[0].ops(), [Op::Push(7), Op::Execute(Command::IntOut)]);
+
+        // Only the push right before the pop is removed.
+        let nodes = optimized("push 5\npush 6\npop\nin num\nout num");
+        assert_eq!(
+            nodes[0].ops(),
+            [
+                Op::Push(5),
+                Op::Execute(Command::IntIn),
+                Op::Execute(Command::IntOut)
+            ]
+        );
+
+        // Dividing by zero is ignored by npiet, but stops a strict program.
+        let source = "push 1\npush 0\ndiv\nout num";
+        let nodes = optimized(source);
+        assert_eq!(
+            nodes[0].ops(),
+            [Op::Push(1), Op::Push(0), Op::Execute(Command::IntOut)]
+        );
+
+        let mut ir = Ir::new(&Cfg::with_dialect(
+            &assemble(source).unwrap(),
+            Dialect::STRICT,
+        ));
+        ir.optimize();
+        assert_eq!(
+            ir.nodes()[0].ops(),
+            [
+                Op::Push(1),
+                Op::Push(0),
+                Op::Execute(Command::Divide),
+                Op::Execute(Command::IntOut)
+            ]
+        );
+    }
+}
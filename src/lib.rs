@@ -1,15 +1,68 @@
 #[macro_use]
 extern crate error_chain;
 
+pub use crate::animate::{animate, render_frame, AnimationOptions};
+pub use crate::asm::assemble;
+pub use crate::blocks::{Block, BlockId, Blocks};
+pub use crate::cfg::{Cfg, Edge, State};
 pub use crate::color::{Color, Hue, Lightness};
-pub use crate::interpreter::Interpreter;
+pub use crate::command::Command;
+pub use crate::compile_c::compile_c;
+pub use crate::compile_rust::compile_rust;
+pub use crate::compile_wat::compile_wat;
+pub use crate::coverage::{Coverage, Exit};
+pub use crate::dead_code::DeadCode;
+pub use crate::depth::{find_underflows, Depth, Underflow};
+pub use crate::dialect::{AtEof, Dialect, Fault, StartOnWhite};
+pub use crate::disasm::disassemble;
+pub use crate::generate::{print_text, print_text_source};
+pub use crate::history::History;
+pub use crate::interpreter::{CodelChooser, DirectionPointer, Interpreter, Snapshot};
+pub use crate::ir::{Ir, Next, Node, NodeId, Op};
+pub use crate::lint::{lint, Finding, Lint, Severity};
+pub use crate::load::{CodelSize, LoadOptions, Transparency, UnknownColor};
+pub use crate::machine::Machine;
+pub use crate::profile::Profile;
 pub use crate::program::Program;
+pub use crate::render::render_trace;
 pub use crate::stack::Stack;
+pub use crate::svg::SvgOptions;
+pub use crate::synth::{Cost, Synthesizer};
+pub use crate::trace::{Step, Trace};
+pub use crate::vm::Vm;
 
+mod animate;
+mod asm;
+mod blocks;
+mod cfg;
 mod color;
 mod command;
-mod errors;
+mod compile_c;
+mod compile_rust;
+mod compile_wat;
+mod coverage;
+mod dead_code;
+mod depth;
+mod dialect;
+#[cfg(test)]
+mod differential;
+mod disasm;
+pub mod errors;
+mod font;
+mod generate;
+mod grid;
+mod history;
 mod interpreter;
+mod ir;
+mod lint;
+mod load;
+mod machine;
+mod profile;
 mod program;
+mod render;
 mod stack;
+mod svg;
+mod synth;
+mod trace;
 pub mod util;
+mod vm;
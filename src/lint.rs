@@ -0,0 +1,445 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+use image::{DynamicImage, GenericImageView, Rgba};
+
+use crate::blocks::Blocks;
+use crate::color::Color;
+use crate::load::{self, CodelSize, LoadOptions, Transparency, UnknownColor};
+use crate::program::Program;
+
+/// Blocks of at most this many codels are tiny enough that touching another
+/// block of the same color diagonally is probably a mistake.
+const TINY_BLOCK: usize = 4;
+
+/// How much a finding matters.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// The program is almost certainly not read the way it was meant to be.
+    Error,
+    /// The program might not be read the way it was meant to be.
+    Warning,
+    /// Something that looks odd, but doesn't change how the program is read.
+    Info,
+}
+
+impl fmt::Display for Severity {
+    /// Write the severity in lowercase, like "warning".
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Info => "info",
+        };
+
+        f.write_str(name)
+    }
+}
+
+/// A kind of problem that `lint` looks for.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Lint {
+    /// The image can't be divided into whole codels.
+    PartialCodels,
+    /// Pixels with an alpha below the threshold.
+    Transparent,
+    /// Colors that aren't one of Piet's 20 colors.
+    NonPaletteColor,
+    /// Codels that aren't all the same color.
+    NonUniformCodel,
+    /// Tiny blocks of the same color that only touch at the corners, so they
+    /// look connected but aren't.
+    DiagonalBlocks,
+}
+
+impl Lint {
+    pub fn severity(self) -> Severity {
+        match self {
+            Lint::PartialCodels | Lint::NonUniformCodel => Severity::Error,
+            Lint::Transparent | Lint::NonPaletteColor => Severity::Warning,
+            Lint::DiagonalBlocks => Severity::Info,
+        }
+    }
+
+    /// A name for the lint in kebab case, like "non-palette-color".
+    pub fn name(self) -> &'static str {
+        match self {
+            Lint::PartialCodels => "partial-codels",
+            Lint::Transparent => "transparent",
+            Lint::NonPaletteColor => "non-palette-color",
+            Lint::NonUniformCodel => "non-uniform-codel",
+            Lint::DiagonalBlocks => "diagonal-blocks",
+        }
+    }
+}
+
+/// A problem found by `lint`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub lint: Lint,
+    /// The pixel where the problem is, or where it starts.
+    pub at: (u32, u32),
+    pub message: String,
+}
+
+impl Finding {
+    pub fn severity(&self) -> Severity {
+        self.lint.severity()
+    }
+
+    /// Write the finding as a JSON object on a single line, with the fields
+    /// `severity`, `lint`, `x`, `y` and `message`.
+    pub fn to_json(&self) -> String {
+        let mut message = String::new();
+        for c in self.message.chars() {
+            match c {
+                '"' => message.push_str("\\\""),
+                '\\' => message.push_str("\\\\"),
+                c if c.is_control() => message.push_str(&format!("\\u{:04x}", c as u32)),
+                c => message.push(c),
+            }
+        }
+
+        format!(
+            "{{\"severity\":\"{}\",\"lint\":\"{}\",\"x\":{},\"y\":{},\"message\":\"{}\"}}",
+            self.severity(),
+            self.lint.name(),
+            self.at.0,
+            self.at.1,
+            message
+        )
+    }
+}
+
+impl fmt::Display for Finding {
+    /// Write the finding like "warning: (3, 0): message [lint-name]".
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: ({}, {}): {} [{}]",
+            self.severity(),
+            self.at.0,
+            self.at.1,
+            self.message,
+            self.lint.name()
+        )
+    }
+}
+
+/// Look for things in an image that make it a worse Piet program, when it's
+/// read with `options`.
+///
+/// Pixels are only ever reported in groups: every pixel of a color that isn't
+/// in the palette is one finding, and so are all the transparent pixels.
+/// Findings are sorted by lint, and then by position.
+pub fn lint(image: &DynamicImage, options: LoadOptions) -> Vec<Finding> {
+    let codel_size = match options.codel_size {
+        CodelSize::Fixed(codel_size) => codel_size.max(1),
+        CodelSize::Auto => load::detect_codel_size(image),
+    } as u32;
+    let (width, height) = image.dimensions();
+    let mut findings = Vec::new();
+
+    if width % codel_size != 0 || height % codel_size != 0 {
+        let at = match width % codel_size {
+            0 => (0, height - height % codel_size),
+            _ => (width - width % codel_size, 0),
+        };
+
+        findings.push(Finding {
+            lint: Lint::PartialCodels,
+            at,
+            message: format!(
+                "the image is {}x{} pixels, which isn't a whole number of {}x{} codels",
+                width, height, codel_size, codel_size
+            ),
+        });
+    }
+
+    let mut transparent = None;
+    let mut unknown_colors = BTreeMap::new();
+
+    for (x, y, Rgba([r, g, b, a])) in image.pixels() {
+        let is_transparent = a < options.alpha_threshold;
+        if is_transparent {
+            transparent.get_or_insert(((y, x), 0)).1 += 1;
+        }
+
+        // Only pixels that are read by their color can have the wrong one.
+        let read_as_color = !is_transparent || options.transparency == Transparency::Ignore;
+        if read_as_color && Color::from_rgb(r, g, b).to_rgb() != (r, g, b) {
+            unknown_colors.entry((r, g, b)).or_insert(((y, x), 0)).1 += 1;
+        }
+    }
+
+    if let Some(((y, x), count)) = transparent {
+        let (pixels, they) = match count {
+            1 => ("this pixel has".to_string(), "it's"),
+            _ => (
+                format!("{} pixels, starting with this one, have", count),
+                "they're",
+            ),
+        };
+        let read_as = match options.transparency {
+            Transparency::Ignore => "read by color anyway, which might not be the color \
+                                     that's shown"
+                .to_string(),
+            Transparency::Black => format!("read as {}", Color::Black),
+            Transparency::White => format!("read as {}", Color::White),
+            Transparency::Error => "rejected".to_string(),
+        };
+
+        findings.push(Finding {
+            lint: Lint::Transparent,
+            at: (x, y),
+            message: format!(
+                "{} an alpha below {}, so {} {}",
+                pixels, options.alpha_threshold, they, read_as
+            ),
+        });
+    }
+
+    let mut unknown_colors = unknown_colors.into_iter().collect::<Vec<_>>();
+    unknown_colors.sort_by_key(|&(_, (first, _))| first);
+
+    for ((r, g, b), ((y, x), count)) in unknown_colors {
+        let read_as = match options.unknown_color {
+            UnknownColor::White => format!("read as {}", Color::White),
+            UnknownColor::Black => format!("read as {}", Color::Black),
+            UnknownColor::Nearest => format!("read as {}", Color::nearest(r, g, b)),
+            UnknownColor::Error => "rejected".to_string(),
+        };
+
+        findings.push(Finding {
+            lint: Lint::NonPaletteColor,
+            at: (x, y),
+            message: format!(
+                "#{:02x}{:02x}{:02x} isn't a Piet color, so {} {}; the nearest color is {}",
+                r,
+                g,
+                b,
+                match count {
+                    1 => "this pixel is".to_string(),
+                    _ => format!("{} pixels, starting with this one, are", count),
+                },
+                read_as,
+                Color::nearest(r, g, b)
+            ),
+        });
+    }
+
+    if codel_size > 1 {
+        for codel_y in (0..height).step_by(codel_size as usize) {
+            for codel_x in (0..width).step_by(codel_size as usize) {
+                let expected = image.get_pixel(codel_x, codel_y);
+                let different = (codel_y..(codel_y + codel_size).min(height))
+                    .flat_map(|y| (codel_x..(codel_x + codel_size).min(width)).map(move |x| (x, y)))
+                    .find(|&(x, y)| image.get_pixel(x, y) != expected);
+
+                if let Some((x, y)) = different {
+                    findings.push(Finding {
+                        lint: Lint::NonUniformCodel,
+                        at: (codel_x, codel_y),
+                        message: format!(
+                            "this codel isn't all one color: it's read as {}, but the pixel \
+                             at ({}, {}) is {}",
+                            hex(expected),
+                            x,
+                            y,
+                            hex(image.get_pixel(x, y))
+                        ),
+                    });
+                }
+            }
+        }
+    }
+
+    findings.extend(diagonal_blocks(image, codel_size, options));
+
+    findings
+}
+
+/// Find tiny blocks that touch a block of the same color diagonally.
+fn diagonal_blocks(image: &DynamicImage, codel_size: u32, options: LoadOptions) -> Vec<Finding> {
+    let options = LoadOptions {
+        codel_size: CodelSize::Fixed(codel_size as usize),
+        ..options
+    };
+    // If the image can't be read, the pixels that are the problem are
+    // reported already.
+    let program = match Program::from_image(image, options) {
+        Ok(program) => program,
+        Err(_) => return Vec::new(),
+    };
+    let blocks = Blocks::new(&program);
+    let mut reported = HashSet::new();
+    let mut findings = Vec::new();
+
+    for ((x, y), color) in program.codels() {
+        if color == Color::Black || color == Color::White {
+            continue;
+        }
+
+        let above = y.checked_sub(1).map(|above| (x + 1, above));
+
+        for corner in Some((x + 1, y + 1)).into_iter().chain(above) {
+            if program.get(corner) != Some(color) {
+                continue;
+            }
+
+            let (a, b) = (blocks.id_at((x, y)).unwrap(), blocks.id_at(corner).unwrap());
+            let smallest = blocks[a].size().min(blocks[b].size());
+
+            if a == b || smallest > TINY_BLOCK || !reported.insert((a.min(b), a.max(b))) {
+                continue;
+            }
+
+            findings.push(Finding {
+                lint: Lint::DiagonalBlocks,
+                at: (x as u32 * codel_size, y as u32 * codel_size),
+                message: format!(
+                    "this {} block only touches the one at codel ({}, {}) diagonally, so \
+                     they're separate blocks",
+                    color, corner.0, corner.1
+                ),
+            });
+        }
+    }
+
+    findings
+}
+
+fn hex(Rgba([r, g, b, a]): Rgba<u8>) -> String {
+    match a {
+        255 => format!("#{:02x}{:02x}{:02x}", r, g, b),
+        _ => format!("#{:02x}{:02x}{:02x}{:02x}", r, g, b, a),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbaImage;
+
+    const RED: Rgba<u8> = Rgba([0xff, 0x00, 0x00, 0xff]);
+    const BLUE: Rgba<u8> = Rgba([0x00, 0x00, 0xff, 0xff]);
+    const BLACK: Rgba<u8> = Rgba([0x00, 0x00, 0x00, 0xff]);
+
+    fn image(width: u32, height: u32, pixels: &[((u32, u32), Rgba<u8>)]) -> DynamicImage {
+        let mut image = RgbaImage::from_pixel(width, height, BLACK);
+        for &((x, y), pixel) in pixels {
+            image.put_pixel(x, y, pixel);
+        }
+
+        DynamicImage::ImageRgba8(image)
+    }
+
+    fn lints(findings: &[Finding]) -> Vec<Lint> {
+        findings.iter().map(|finding| finding.lint).collect()
+    }
+
+    #[test]
+    fn test_lint_clean() {
+        let clean = image(4, 4, &[((0, 0), RED), ((1, 0), RED), ((2, 0), BLUE)]);
+        assert_eq!(lint(&clean, LoadOptions::default()), []);
+
+        // The detected codel size fits the image, whatever it is.
+        let odd = image(4, 4, &[((1, 0), RED)]);
+        let auto = LoadOptions {
+            codel_size: CodelSize::Auto,
+            ..LoadOptions::default()
+        };
+        let two = LoadOptions {
+            codel_size: CodelSize::Fixed(2),
+            ..LoadOptions::default()
+        };
+        assert_eq!(lint(&odd, auto), []);
+        assert_eq!(lints(&lint(&odd, two)), [Lint::NonUniformCodel]);
+    }
+
+    #[test]
+    fn test_lint_pixels() {
+        let findings = lint(
+            &image(
+                3,
+                2,
+                &[
+                    ((1, 0), Rgba([0xfe, 0x01, 0x00, 0xff])),
+                    ((2, 0), Rgba([0xfe, 0x01, 0x00, 0xff])),
+                    ((0, 1), Rgba([0xff, 0x00, 0x00, 0x00])),
+                ],
+            ),
+            LoadOptions {
+                codel_size: CodelSize::Fixed(2),
+                ..LoadOptions::default()
+            },
+        );
+
+        assert_eq!(
+            lints(&findings),
+            [
+                Lint::PartialCodels,
+                Lint::Transparent,
+                Lint::NonPaletteColor,
+                Lint::NonUniformCodel,
+                Lint::NonUniformCodel,
+            ]
+        );
+        assert_eq!(findings[0].at, (2, 0));
+        assert_eq!(findings[1].at, (0, 1));
+        assert_eq!(
+            findings[1].message,
+            "this pixel has an alpha below 128, so it's read by color anyway, which might \
+             not be the color that's shown"
+        );
+        assert_eq!(
+            findings[2].to_string(),
+            "warning: (1, 0): #fe0100 isn't a Piet color, so 2 pixels, starting with this \
+             one, are read as white; the nearest color is red [non-palette-color]"
+        );
+        assert_eq!(findings[3].severity(), Severity::Error);
+        assert_eq!(
+            findings[3].to_json(),
+            "{\"severity\":\"error\",\"lint\":\"non-uniform-codel\",\"x\":0,\"y\":0,\
+             \"message\":\"this codel isn't all one color: it's read as #000000, but the \
+             pixel at (1, 0) is #fe0100\"}"
+        );
+    }
+
+    #[test]
+    fn test_lint_alpha_threshold() {
+        const OFF: Rgba<u8> = Rgba([0xfe, 0x01, 0x00, 0xc8]);
+        const CLEAR: Rgba<u8> = Rgba([0xfe, 0x01, 0x00, 0x00]);
+
+        // An alpha of 200 is above the threshold, so the color is read.
+        let findings = lint(&image(2, 1, &[((0, 0), OFF)]), LoadOptions::default());
+        assert_eq!(lints(&findings), [Lint::NonPaletteColor]);
+
+        // Transparent pixels are only read by their color when that's ignored.
+        let findings = lint(&image(2, 1, &[((0, 0), CLEAR)]), LoadOptions::default());
+        assert_eq!(lints(&findings), [Lint::Transparent, Lint::NonPaletteColor]);
+
+        let black = LoadOptions {
+            transparency: Transparency::Black,
+            ..LoadOptions::default()
+        };
+        let findings = lint(&image(2, 1, &[((0, 0), CLEAR), ((1, 0), CLEAR)]), black);
+        assert_eq!(lints(&findings), [Lint::Transparent]);
+        assert_eq!(
+            findings[0].message,
+            "2 pixels, starting with this one, have an alpha below 128, so they're read as black"
+        );
+    }
+
+    #[test]
+    fn test_lint_diagonal_blocks() {
+        let findings = lint(
+            &image(3, 3, &[((0, 0), RED), ((1, 1), RED), ((2, 2), BLUE)]),
+            LoadOptions::default(),
+        );
+
+        assert_eq!(lints(&findings), [Lint::DiagonalBlocks]);
+        assert_eq!(findings[0].at, (0, 0));
+        assert!(findings[0].message.contains("codel (1, 1)"));
+    }
+}
@@ -0,0 +1,331 @@
+use std::fs;
+use std::path::Path;
+
+use image::{DynamicImage, GenericImageView, Rgba};
+
+use crate::color::Color;
+use crate::errors::*;
+use crate::program::Program;
+
+/// What pixels with an alpha below the threshold are read as.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Transparency {
+    /// Read their color like any other pixel's. Encoders often leave
+    /// arbitrary colors behind in pixels that are fully transparent, so this
+    /// is only safe for images that are opaque.
+    Ignore,
+    Black,
+    White,
+    /// Fail with `ErrorKind::TransparentPixel`.
+    Error,
+}
+
+/// How many pixels wide and high each codel is.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CodelSize {
+    Fixed(usize),
+    /// The largest size that every run of same-colored pixels, and the image
+    /// itself, is a multiple of. For images that were scaled up without
+    /// smoothing, this is the size they were scaled up by.
+    Auto,
+}
+
+/// What pixels are read as whose color isn't one of Piet's 20 colors.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum UnknownColor {
+    White,
+    Black,
+    /// The closest color in the palette, according to `Color::nearest`.
+    Nearest,
+    /// Fail with `ErrorKind::UnknownColor`.
+    Error,
+}
+
+/// Settings for `Program::from_image`, `Program::from_bytes` and
+/// `Program::open`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LoadOptions {
+    pub codel_size: CodelSize,
+    pub unknown_color: UnknownColor,
+    pub transparency: Transparency,
+    /// Pixels with an alpha below this are transparent.
+    pub alpha_threshold: u8,
+}
+
+impl Default for LoadOptions {
+    fn default() -> Self {
+        LoadOptions {
+            codel_size: CodelSize::Fixed(1),
+            unknown_color: UnknownColor::White,
+            transparency: Transparency::Ignore,
+            alpha_threshold: 128,
+        }
+    }
+}
+
+impl Program {
+    /// Read a program from an image. The color of each codel is the color of
+    /// its top left pixel.
+    pub fn from_image(image: &DynamicImage, options: LoadOptions) -> Result<Program> {
+        let codel_size = match options.codel_size {
+            CodelSize::Fixed(codel_size) => codel_size.max(1),
+            CodelSize::Auto => detect_codel_size(image),
+        };
+        let (width, height) = image.dimensions();
+        let size = (
+            (width as usize).div_ceil(codel_size),
+            (height as usize).div_ceil(codel_size),
+        );
+
+        let mut colors = Vec::with_capacity(size.0 * size.1);
+        for y in (0..height).step_by(codel_size) {
+            for x in (0..width).step_by(codel_size) {
+                colors.push(read_pixel(image.get_pixel(x, y), (x, y), options)?);
+            }
+        }
+
+        Ok(Program::new(size, colors))
+    }
+
+    /// Read a program from an image file that's already in memory. The format
+    /// is detected from the first few bytes, so PNG, GIF, BMP and PPM all
+    /// work.
+    pub fn from_bytes(bytes: &[u8], options: LoadOptions) -> Result<Program> {
+        Program::from_image(&image::load_from_memory(bytes)?, options)
+    }
+
+    /// Read a program from a file. The format is guessed from the file's
+    /// extension: `.txt` files are read with `from_grid`, and anything else
+    /// is read as an image.
+    pub fn open<P: AsRef<Path>>(path: P, options: LoadOptions) -> Result<Program> {
+        let path = path.as_ref();
+
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("txt") => Program::from_grid(&fs::read_to_string(path)?),
+            _ => Program::from_image(&image::open(path)?, options),
+        }
+    }
+}
+
+fn read_pixel(pixel: Rgba<u8>, at: (u32, u32), options: LoadOptions) -> Result<Color> {
+    let Rgba([r, g, b, a]) = pixel;
+
+    if a < options.alpha_threshold {
+        match options.transparency {
+            Transparency::Ignore => {}
+            Transparency::Black => return Ok(Color::Black),
+            Transparency::White => return Ok(Color::White),
+            Transparency::Error => return Err(ErrorKind::TransparentPixel(at.0, at.1).into()),
+        }
+    }
+
+    let color = Color::from_rgb(r, g, b);
+    if color.to_rgb() == (r, g, b) {
+        return Ok(color);
+    }
+
+    match options.unknown_color {
+        UnknownColor::White => Ok(Color::White),
+        UnknownColor::Black => Ok(Color::Black),
+        UnknownColor::Nearest => Ok(Color::nearest(r, g, b)),
+        UnknownColor::Error => Err(ErrorKind::UnknownColor(at.0, at.1, (r, g, b)).into()),
+    }
+}
+
+/// Find the codel size for `CodelSize::Auto`.
+pub(crate) fn detect_codel_size(image: &DynamicImage) -> usize {
+    let (width, height) = image.dimensions();
+    let mut size = gcd(width, height);
+
+    // The length of every run of same-colored pixels, across and down.
+    let runs = |outer: u32, inner: u32, pixel: &dyn Fn(u32, u32) -> Rgba<u8>| {
+        let mut size = 0;
+        for i in 0..outer {
+            let mut start = 0;
+            for j in 1..=inner {
+                if j == inner || pixel(i, j) != pixel(i, start) {
+                    size = gcd(size, j - start);
+                    start = j;
+                }
+            }
+        }
+        size
+    };
+
+    size = gcd(size, runs(height, width, &|y, x| image.get_pixel(x, y)));
+    size = gcd(size, runs(width, height, &|x, y| image.get_pixel(x, y)));
+
+    size.max(1) as usize
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    match b {
+        0 => a,
+        _ => gcd(b, a % b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::{Hue::*, Lightness::*};
+    use image::RgbaImage;
+
+    const RED: Rgba<u8> = Rgba([0xff, 0x00, 0x00, 0xff]);
+    const CLEAR_RED: Rgba<u8> = Rgba([0xff, 0x00, 0x00, 0x00]);
+
+    #[test]
+    fn test_program_from_image() {
+        let mut image = RgbaImage::from_pixel(5, 3, RED);
+        image.put_pixel(2, 0, Rgba([0x00, 0x00, 0x00, 0xff]));
+        let image = DynamicImage::ImageRgba8(image);
+
+        let options = LoadOptions {
+            codel_size: CodelSize::Fixed(2),
+            ..LoadOptions::default()
+        };
+        let program = Program::from_image(&image, options).unwrap();
+
+        assert_eq!(program.size(), (3, 2));
+        assert_eq!(program.get((0, 0)), Some(Color::Composite(Red, Normal)));
+        assert_eq!(program.get((1, 0)), Some(Color::Black));
+        assert_eq!(program.get((2, 1)), Some(Color::Composite(Red, Normal)));
+    }
+
+    #[test]
+    fn test_program_from_image_transparency() {
+        let mut image = RgbaImage::from_pixel(2, 1, RED);
+        image.put_pixel(1, 0, CLEAR_RED);
+        let image = DynamicImage::ImageRgba8(image);
+
+        let read = |transparency| {
+            let options = LoadOptions {
+                transparency,
+                ..LoadOptions::default()
+            };
+            Program::from_image(&image, options).map(|program| program.get((1, 0)).unwrap())
+        };
+
+        assert_eq!(
+            read(Transparency::Ignore).unwrap(),
+            Color::Composite(Red, Normal)
+        );
+        assert_eq!(read(Transparency::Black).unwrap(), Color::Black);
+        assert_eq!(read(Transparency::White).unwrap(), Color::White);
+        match read(Transparency::Error) {
+            Err(Error(ErrorKind::TransparentPixel(1, 0), _)) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+
+        // Opaque enough pixels are read as usual.
+        let options = LoadOptions {
+            transparency: Transparency::Error,
+            alpha_threshold: 0,
+            ..LoadOptions::default()
+        };
+        assert!(Program::from_image(&image, options).is_ok());
+    }
+
+    #[test]
+    fn test_program_from_image_unknown_color() {
+        let image = RgbaImage::from_pixel(1, 1, Rgba([0xf0, 0x10, 0x08, 0xff]));
+        let image = DynamicImage::ImageRgba8(image);
+
+        let read = |unknown_color| {
+            let options = LoadOptions {
+                unknown_color,
+                ..LoadOptions::default()
+            };
+            Program::from_image(&image, options).map(|program| program.get((0, 0)).unwrap())
+        };
+
+        assert_eq!(read(UnknownColor::White).unwrap(), Color::White);
+        assert_eq!(read(UnknownColor::Black).unwrap(), Color::Black);
+        assert_eq!(
+            read(UnknownColor::Nearest).unwrap(),
+            Color::Composite(Red, Normal)
+        );
+        match read(UnknownColor::Error) {
+            Err(Error(ErrorKind::UnknownColor(0, 0, (0xf0, 0x10, 0x08)), _)) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_program_from_image_auto_codel_size() {
+        use crate::asm::assemble;
+
+        let program = assemble("push 3\npush 2\nsub\nout num").unwrap();
+        let image = DynamicImage::ImageRgb8(program.to_image(3));
+
+        let options = LoadOptions {
+            codel_size: CodelSize::Auto,
+            ..LoadOptions::default()
+        };
+        let loaded = Program::from_image(&image, options).unwrap();
+
+        assert_eq!(loaded.size(), program.size());
+        assert!(loaded.codels().eq(program.codels()));
+
+        // A single odd pixel makes every codel one pixel.
+        let mut image = image.to_rgba8();
+        image.put_pixel(1, 0, Rgba([0xff, 0xff, 0xff, 0xff]));
+        let image = DynamicImage::ImageRgba8(image);
+        assert_eq!(detect_codel_size(&image), 1);
+    }
+
+    #[test]
+    fn test_program_from_bytes() {
+        use image::ImageOutputFormat;
+
+        let program = Program::new((2, 1), vec![Color::Composite(Cyan, Dark), Color::Black]);
+        let image = DynamicImage::ImageRgb8(program.to_image(1));
+
+        for format in [
+            ImageOutputFormat::Png,
+            ImageOutputFormat::Gif,
+            ImageOutputFormat::Bmp,
+        ] {
+            let mut bytes = Vec::new();
+            image.write_to(&mut bytes, format).unwrap();
+
+            let loaded = Program::from_bytes(&bytes, LoadOptions::default()).unwrap();
+            assert!(loaded.codels().eq(program.codels()));
+        }
+
+        let ppm = b"P6 2 1 255 \x00\xc0\xc0\x00\x00\x00";
+        let loaded = Program::from_bytes(ppm, LoadOptions::default()).unwrap();
+        assert!(loaded.codels().eq(program.codels()));
+
+        assert!(Program::from_bytes(b"lr nr", LoadOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_program_open() {
+        use crate::differential;
+
+        let dir = differential::scratch_dir("open");
+        let path = dir.join("program.png");
+        let program = Program::new((2, 1), vec![Color::Black, Color::White]);
+        program.to_image(2).save(&path).unwrap();
+
+        let options = LoadOptions {
+            codel_size: CodelSize::Auto,
+            ..LoadOptions::default()
+        };
+        assert!(Program::open(&path, options)
+            .unwrap()
+            .codels()
+            .eq(program.codels()));
+        assert!(Program::open(dir.join("missing.png"), options).is_err());
+
+        let path = dir.join("program.txt");
+        fs::write(&path, program.to_grid()).unwrap();
+        assert!(Program::open(&path, options)
+            .unwrap()
+            .codels()
+            .eq(program.codels()));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,410 @@
+use crate::command::Command;
+use crate::dialect::{AtEof, Dialect, Fault};
+use crate::errors::*;
+use crate::stack::Stack;
+
+/// Everything that commands act on: the stack, the input that's left to read,
+/// and the output written so far.
+///
+/// This is what executes commands for `Interpreter`, and for compiled
+/// programs, so they always behave the same way.
+#[derive(Debug, Default, Clone)]
+pub struct Machine {
+    stack: Stack,
+    input: String,
+    input_cursor: usize,
+    output: String,
+    dialect: Dialect,
+}
+
+impl Machine {
+    pub fn new() -> Self {
+        Machine::default()
+    }
+
+    /// A machine with values already on the stack, and no input.
+    pub(crate) fn with_stack(stack: Stack) -> Self {
+        Machine {
+            stack,
+            ..Machine::default()
+        }
+    }
+
+    /// Choose how to execute commands that the specification doesn't say how
+    /// to execute.
+    pub fn set_dialect(&mut self, dialect: Dialect) {
+        self.dialect = dialect;
+    }
+
+    pub fn dialect(&self) -> Dialect {
+        self.dialect
+    }
+
+    /// Append text to the input that `IntIn` and `CharIn` read from.
+    pub fn feed_input(&mut self, input: &str) {
+        self.input.push_str(input);
+    }
+
+    pub fn stack(&self) -> &Stack {
+        &self.stack
+    }
+
+    /// Everything that has been written so far.
+    pub fn output(&self) -> &str {
+        &self.output
+    }
+
+    /// How much of the input has been read, in bytes.
+    pub(crate) fn input_cursor(&self) -> usize {
+        self.input_cursor
+    }
+
+    /// Go back to an earlier state. Input is only ever appended to, and output
+    /// only grows, so only their lengths are needed.
+    pub(crate) fn rewind(&mut self, stack: &Stack, input_cursor: usize, output_len: usize) {
+        self.stack.clone_from(stack);
+        self.input_cursor = input_cursor;
+        self.output.truncate(output_len);
+    }
+
+    /// Push any value, which `Command::Push` can't always do.
+    pub fn push(&mut self, value: i64) {
+        self.stack.push(value);
+    }
+
+    /// Execute a command. `block_size` is the size of the color block that
+    /// was just left, which is the value that `Push` pushes.
+    ///
+    /// Commands that can't be executed because the stack doesn't have enough
+    /// values are ignored. What happens when the values don't make sense is up
+    /// to the dialect, but commands that would fail are ignored too.
+    ///
+    /// # Returns
+    ///
+    /// For `Pointer`, how many times to rotate the DP clockwise, and for
+    /// `Switch`, how many times to switch the CC. Both are 0 if the stack was
+    /// empty, and so is the result of every other command.
+    pub fn execute(&mut self, command: Command, block_size: usize) -> u8 {
+        self.try_execute(command, block_size).unwrap_or(0)
+    }
+
+    /// Execute a command like `execute` does, but fail with
+    /// `ErrorKind::CommandError` where the dialect says to, without changing
+    /// anything.
+    pub fn try_execute(&mut self, command: Command, block_size: usize) -> Result<u8> {
+        let dialect = self.dialect;
+        let second = self
+            .stack
+            .len()
+            .checked_sub(2)
+            .map(|index| self.stack[index]);
+
+        match command {
+            Command::Divide | Command::Mod if second.is_some() && self.stack.peek() == Some(&0) => {
+                self.fault(command, dialect.division_by_zero)
+            }
+            Command::Roll if second.is_some_and(|depth| depth < 0) => {
+                self.fault(command, dialect.negative_roll)
+            }
+            Command::CharIn if self.remaining_input().is_empty() => match dialect.char_in_at_eof {
+                AtEof::Ignore => Ok(0),
+                AtEof::Push(value) => {
+                    self.stack.push(value);
+                    Ok(0)
+                }
+                AtEof::Error => Err(ErrorKind::CommandError(command).into()),
+            },
+            _ => Ok(self.execute_valid(command, block_size)),
+        }
+    }
+
+    fn fault(&mut self, command: Command, fault: Fault) -> Result<u8> {
+        match fault {
+            Fault::Ignore => {}
+            Fault::Discard => {
+                self.stack.pop2();
+            }
+            Fault::Error => return Err(ErrorKind::CommandError(command).into()),
+        }
+
+        Ok(0)
+    }
+
+    /// Execute a command, once it's known that the dialect doesn't matter.
+    fn execute_valid(&mut self, command: Command, block_size: usize) -> u8 {
+        match command {
+            Command::Push => {
+                self.stack.push(block_size as i64);
+            }
+
+            Command::Pop => {
+                self.stack.pop();
+            }
+
+            Command::Add => {
+                self.stack.fold_top(|b, a| a.wrapping_add(b));
+            }
+
+            Command::Subtract => {
+                self.stack.fold_top(|b, a| a.wrapping_sub(b));
+            }
+
+            Command::Multiply => {
+                self.stack.fold_top(|b, a| a.wrapping_mul(b));
+            }
+
+            Command::Divide => {
+                if self.stack.peek() != Some(&0) {
+                    self.stack.fold_top(|b, a| a.wrapping_div(b));
+                }
+            }
+
+            Command::Mod => {
+                // The result has the same sign as the divisor.
+                if self.stack.peek() != Some(&0) {
+                    self.stack.fold_top(|b, a| {
+                        let r = a.wrapping_rem(b);
+                        if r != 0 && (r < 0) != (b < 0) {
+                            r + b
+                        } else {
+                            r
+                        }
+                    });
+                }
+            }
+
+            Command::Not => {
+                self.stack.map_top(|value| if value != 0 { 0 } else { 1 });
+            }
+
+            Command::Greater => {
+                self.stack.fold_top(|b, a| if a > b { 1 } else { 0 });
+            }
+
+            Command::Pointer => {
+                if let Some(value) = self.stack.pop() {
+                    return value.rem_euclid(4) as u8;
+                }
+            }
+
+            Command::Switch => {
+                if let Some(value) = self.stack.pop() {
+                    return value.rem_euclid(2) as u8;
+                }
+            }
+
+            Command::Duplicate => {
+                if let Some(&value) = self.stack.last() {
+                    self.stack.push(value);
+                }
+            }
+
+            Command::Roll => {
+                let len = self.stack.len();
+
+                match self.stack.pop2() {
+                    (Some(_), Some(0)) => {}
+                    (Some(times), Some(depth)) if depth > 0 && (depth as usize) <= len - 2 => {
+                        self.stack.roll(depth as usize - 1, times.rem_euclid(depth));
+                    }
+                    (Some(times), Some(depth)) => {
+                        self.stack.push(depth);
+                        self.stack.push(times);
+                    }
+                    (Some(times), None) => self.stack.push(times),
+                    _ => {}
+                }
+            }
+
+            Command::IntIn => {
+                if let Some(value) = self.read_int() {
+                    self.stack.push(value);
+                }
+            }
+
+            Command::CharIn => {
+                if let Some(value) = self.read_char() {
+                    self.stack.push(value as i64);
+                }
+            }
+
+            Command::IntOut => {
+                if let Some(value) = self.stack.pop() {
+                    self.output.push_str(&value.to_string());
+                }
+            }
+
+            Command::CharOut => {
+                if let Some(value) = self.stack.pop() {
+                    match std::char::from_u32(value as u32).filter(|_| value >= 0) {
+                        Some(c) => self.output.push(c),
+                        None => self.stack.push(value),
+                    }
+                }
+            }
+
+            Command::NoOp => {}
+        }
+
+        0
+    }
+
+    /// Whether executing a command would read input, but there's none left
+    /// that it could read. An integer can't be read from whitespace alone.
+    pub fn needs_input(&self, command: Command) -> bool {
+        match command {
+            Command::IntIn => self.remaining_input().trim_start().is_empty(),
+            Command::CharIn => self.remaining_input().is_empty(),
+            _ => false,
+        }
+    }
+
+    fn remaining_input(&self) -> &str {
+        &self.input[self.input_cursor..]
+    }
+
+    /// Read a character, the way `CharIn` does.
+    pub fn read_char(&mut self) -> Option<char> {
+        let c = self.remaining_input().chars().next()?;
+        self.input_cursor += c.len_utf8();
+
+        Some(c)
+    }
+
+    /// Read an integer the way `IntIn` does, skipping any whitespace before
+    /// it.
+    ///
+    /// If the input doesn't start with an integer, nothing is read.
+    pub fn read_int(&mut self) -> Option<i64> {
+        let remaining = self.remaining_input();
+        let trimmed = remaining.trim_start();
+        let skipped = remaining.len() - trimmed.len();
+
+        let sign_len = if trimmed.starts_with(['-', '+']) {
+            1
+        } else {
+            0
+        };
+        let digits_len = trimmed[sign_len..]
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(trimmed.len() - sign_len);
+
+        if digits_len == 0 {
+            return None;
+        }
+
+        let value = trimmed[..sign_len + digits_len].parse().ok()?;
+        self.input_cursor += skipped + sign_len + digits_len;
+
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_machine_input() {
+        let mut machine = Machine::new();
+        machine.feed_input("  -12 x");
+
+        assert!(!machine.needs_input(Command::IntIn));
+        assert_eq!(machine.read_int(), Some(-12));
+        assert_eq!(machine.read_int(), None);
+        assert_eq!(machine.read_char(), Some(' '));
+        assert!(!machine.needs_input(Command::CharIn));
+        assert_eq!(machine.read_char(), Some('x'));
+        assert_eq!(machine.read_char(), None);
+        assert!(machine.needs_input(Command::CharIn));
+
+        // Whitespace can be read as a character, but not as an integer.
+        machine.feed_input("\n");
+        assert!(machine.needs_input(Command::IntIn));
+        assert!(!machine.needs_input(Command::CharIn));
+        assert!(!machine.needs_input(Command::Add));
+    }
+
+    #[test]
+    fn test_machine_execute() {
+        let mut machine = Machine::new();
+        let mut run = |stack: Vec<i64>, command| {
+            machine.stack = Stack::from_vec(stack);
+            machine.execute(command, 1);
+            machine.stack.to_vec()
+        };
+
+        assert_eq!(run(vec![7, 2], Command::Subtract), vec![5]);
+        assert_eq!(run(vec![7, 2], Command::Multiply), vec![14]);
+        assert_eq!(run(vec![7, 2], Command::Divide), vec![3]);
+        assert_eq!(run(vec![7, 0], Command::Divide), vec![7, 0]);
+        assert_eq!(run(vec![-7, 3], Command::Mod), vec![2]);
+        assert_eq!(run(vec![7, -3], Command::Mod), vec![-2]);
+        assert_eq!(run(vec![-1, i64::MIN], Command::Mod), vec![-1]);
+        assert_eq!(
+            run(vec![i64::MAX - 1, i64::MAX], Command::Mod),
+            vec![i64::MAX - 1]
+        );
+        assert_eq!(run(vec![3, 0], Command::Not), vec![3, 1]);
+        assert_eq!(run(vec![3, 2], Command::Greater), vec![1]);
+        assert_eq!(run(vec![1, 2, 3, 3, 1], Command::Roll), vec![3, 1, 2]);
+        assert_eq!(run(vec![1, 2, 3, 2, -1], Command::Roll), vec![1, 3, 2]);
+        assert_eq!(run(vec![1, 2, 3, 5, 1], Command::Roll), vec![1, 2, 3, 5, 1]);
+        assert_eq!(run(vec![1, 2, -1, 1], Command::Roll), vec![1, 2, -1, 1]);
+    }
+
+    #[test]
+    fn test_machine_branches() {
+        let mut machine = Machine::new();
+
+        assert_eq!(machine.execute(Command::Pointer, 1), 0);
+        machine.execute(Command::Push, 7);
+        assert_eq!(machine.execute(Command::Pointer, 1), 3);
+        machine.execute(Command::Push, 3);
+        assert_eq!(machine.execute(Command::Switch, 1), 1);
+        assert!(machine.stack().is_empty());
+    }
+
+    #[test]
+    fn test_machine_dialect() {
+        use crate::dialect::{AtEof, Dialect, Fault};
+
+        let run = |dialect: Dialect, stack: Vec<i64>, command| {
+            let mut machine = Machine::new();
+            machine.set_dialect(dialect);
+            machine.stack = Stack::from_vec(stack);
+            machine.try_execute(command, 1).ok()?;
+            Some(machine.stack.to_vec())
+        };
+
+        let discard = Dialect {
+            negative_roll: Fault::Discard,
+            division_by_zero: Fault::Discard,
+            char_in_at_eof: AtEof::Push(-1),
+            ..Dialect::NPIET
+        };
+
+        assert_eq!(
+            run(Dialect::NPIET, vec![7, 0], Command::Divide),
+            Some(vec![7, 0])
+        );
+        assert_eq!(run(discard, vec![7, 0], Command::Mod), Some(vec![]));
+        assert_eq!(run(Dialect::STRICT, vec![7, 0], Command::Divide), None);
+        // Only one value is never a division by zero, just an underflow.
+        assert_eq!(
+            run(Dialect::STRICT, vec![0], Command::Divide),
+            Some(vec![0])
+        );
+
+        assert_eq!(
+            run(Dialect::NPIET, vec![1, -1, 1], Command::Roll),
+            Some(vec![1, -1, 1])
+        );
+        assert_eq!(run(discard, vec![1, -1, 1], Command::Roll), Some(vec![1]));
+        assert_eq!(run(Dialect::STRICT, vec![1, -1, 1], Command::Roll), None);
+
+        assert_eq!(run(Dialect::NPIET, vec![], Command::CharIn), Some(vec![]));
+        assert_eq!(run(discard, vec![], Command::CharIn), Some(vec![-1]));
+        assert_eq!(run(Dialect::STRICT, vec![], Command::CharIn), None);
+    }
+}
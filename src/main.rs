@@ -1,36 +1,701 @@
-use std::path::PathBuf;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
 
-use image::{self, GenericImageView};
+use image::DynamicImage;
+use structopt::clap;
 use structopt::StructOpt;
 
-use piet::util;
-use piet::{Color, Interpreter, Program};
+use piet::{
+    animate, assemble, compile_c, compile_rust, compile_wat, disassemble, find_underflows, lint,
+    print_text, render_trace, AnimationOptions, Cfg, CodelSize, Coverage, DeadCode, Dialect,
+    Interpreter, LoadOptions, Profile, Program, Severity, SvgOptions, Trace, Transparency,
+    UnknownColor, Vm,
+};
 
 #[derive(Debug, StructOpt)]
 struct Opt {
     /// Piet source file.
     ///
-    /// Supported file types: PNG, GIF, BMP.
+    /// Supported file types: PNG, GIF, BMP, PPM, and the text grid format
+    /// for files ending in `.txt`. With `-`, an image is read from stdin, and
+    /// the program gets no input.
     #[structopt(name = "SOURCE", parse(from_os_str))]
-    source_path: PathBuf,
+    source_path: Option<PathBuf>,
 
-    /// Width & height to read codels at.
+    #[structopt(flatten)]
+    load_flags: LoadFlags,
+
+    /// Stop after this many steps, even if the program hasn't halted.
+    #[structopt(long)]
+    max_steps: Option<u64>,
+
+    /// Stop with an error if the program gets stuck in a loop that can never
+    /// read or write anything.
+    #[structopt(long)]
+    detect_loops: bool,
+
+    #[structopt(flatten)]
+    dialect_flags: DialectFlags,
+
+    /// How to run the program. The VM is faster, but it can't record a trace,
+    /// so it can't be combined with options that need one.
+    #[structopt(long, default_value = "interpreter", possible_values = &["interpreter", "vm"])]
+    engine: String,
+
+    /// Write an image of the path the program took to this file.
+    #[structopt(long, parse(from_os_str))]
+    trace_image: Option<PathBuf>,
+
+    /// Print statistics about where the program spends its steps to stderr.
+    #[structopt(long)]
+    profile: bool,
+
+    /// Write a heatmap of how often each color block was visited to this
+    /// file.
+    #[structopt(long, parse(from_os_str))]
+    heatmap: Option<PathBuf>,
+
+    /// Print how much of the program was executed to stderr.
+    #[structopt(long)]
+    coverage: bool,
+
+    /// Write an image of the program with the color blocks that were never
+    /// entered crossed out to this file.
+    #[structopt(long, parse(from_os_str))]
+    coverage_image: Option<PathBuf>,
+
+    /// Merge the coverage into this file, so it can be combined across runs.
+    #[structopt(long, parse(from_os_str))]
+    coverage_data: Option<PathBuf>,
+
+    /// Write the program's control flow graph to this file, in Graphviz's DOT
+    /// language.
+    #[structopt(long, parse(from_os_str))]
+    cfg: Option<PathBuf>,
+
+    /// Write an animated GIF of the program's execution to this file. Unless
+    /// --max-steps is given, it stops after 1000 steps.
+    #[structopt(long, parse(from_os_str))]
+    animate: Option<PathBuf>,
+
+    /// Draw a frame of the animation every this many steps.
+    #[structopt(long, default_value = "1")]
+    frame_every: u64,
+
+    /// How many pixels wide each codel is drawn in the trace image and
+    /// animation.
+    #[structopt(long, default_value = "16")]
+    scale: u32,
+
+    #[structopt(subcommand)]
+    tool: Option<Tool>,
+}
+
+/// How to read a program from an image.
+#[derive(Debug, StructOpt)]
+struct LoadFlags {
+    /// Width & height to read codels at. If it's not given, it's detected
+    /// from the image.
     #[structopt(short, long)]
-    codel_size: usize,
+    codel_size: Option<usize>,
+
+    /// What to read pixels as whose color isn't a Piet color: white, black,
+    /// the nearest Piet color, or an error.
+    #[structopt(long, default_value = "white", possible_values = &["white", "black", "nearest", "error"])]
+    unknown_color: String,
+
+    /// What to read pixels as that are more transparent than
+    /// --alpha-threshold: their color, black, white, or an error.
+    #[structopt(long, default_value = "ignore", possible_values = &["ignore", "black", "white", "error"])]
+    transparent: String,
+
+    /// Pixels with an alpha below this are transparent.
+    #[structopt(long, default_value = "128")]
+    alpha_threshold: u8,
+}
+
+impl LoadFlags {
+    fn options(&self) -> LoadOptions {
+        LoadOptions {
+            codel_size: self.codel_size.map_or(CodelSize::Auto, CodelSize::Fixed),
+            unknown_color: match self.unknown_color.as_str() {
+                "black" => UnknownColor::Black,
+                "nearest" => UnknownColor::Nearest,
+                "error" => UnknownColor::Error,
+                _ => UnknownColor::White,
+            },
+            transparency: match self.transparent.as_str() {
+                "black" => Transparency::Black,
+                "white" => Transparency::White,
+                "error" => Transparency::Error,
+                _ => Transparency::Ignore,
+            },
+            alpha_threshold: self.alpha_threshold,
+        }
+    }
+}
+
+/// How to run a program, or how running it is modeled.
+#[derive(Debug, StructOpt)]
+struct DialectFlags {
+    /// How to handle the cases the Piet specification is ambiguous about:
+    /// "npiet" ignores commands that can't be executed, and "strict" stops
+    /// with an error.
+    #[structopt(long, default_value = "npiet", possible_values = &["npiet", "strict"])]
+    dialect: String,
+}
+
+impl DialectFlags {
+    fn dialect(&self) -> Dialect {
+        match self.dialect.as_str() {
+            "strict" => Dialect::STRICT,
+            _ => Dialect::NPIET,
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+enum Tool {
+    /// Turn a program written in Piet assembly into an image.
+    Asm {
+        /// Assembly source file.
+        #[structopt(name = "SOURCE", parse(from_os_str))]
+        source_path: PathBuf,
+
+        /// The file to write: an image, or a text grid if it ends in `.txt`.
+        #[structopt(short, long, parse(from_os_str))]
+        output: PathBuf,
+
+        /// Width & height to draw codels at.
+        #[structopt(short, long, default_value = "1")]
+        codel_size: u32,
+    },
+
+    /// Generate a program that prints some text.
+    Text {
+        /// The text to print.
+        text: String,
+
+        /// The file to write: an image, or a text grid if it ends in `.txt`.
+        #[structopt(short, long, parse(from_os_str))]
+        output: PathBuf,
+
+        /// Width & height to draw codels at.
+        #[structopt(short, long, default_value = "1")]
+        codel_size: u32,
+    },
+
+    /// Compile a program ahead of time to source code in another language.
+    Compile {
+        /// Piet source file.
+        #[structopt(name = "SOURCE", parse(from_os_str))]
+        source_path: PathBuf,
+
+        #[structopt(flatten)]
+        load_flags: LoadFlags,
+
+        #[structopt(flatten)]
+        dialect_flags: DialectFlags,
+
+        /// The language to compile to.
+        #[structopt(long, default_value = "c", possible_values = &["c", "rust", "wat"])]
+        target: String,
+
+        /// The file to write, instead of stdout.
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+
+    /// Look for mistakes in a program without running it.
+    Check {
+        /// Piet source file.
+        #[structopt(name = "SOURCE", parse(from_os_str))]
+        source_path: PathBuf,
+
+        #[structopt(flatten)]
+        load_flags: LoadFlags,
+
+        #[structopt(flatten)]
+        dialect_flags: DialectFlags,
+
+        /// Write an image of the program with the color blocks that can never
+        /// be entered grayed out to this file.
+        #[structopt(long, parse(from_os_str))]
+        dead_code_image: Option<PathBuf>,
+
+        /// How many pixels wide each codel is drawn in the image.
+        #[structopt(long, default_value = "16")]
+        scale: u32,
+    },
+
+    /// Look for problems in an image that make it a worse Piet program.
+    ///
+    /// Exits with status 1 if anything is found that changes how the program
+    /// is read.
+    Lint {
+        /// Piet source file.
+        #[structopt(name = "SOURCE", parse(from_os_str))]
+        source_path: PathBuf,
+
+        #[structopt(flatten)]
+        load_flags: LoadFlags,
+
+        /// How to print findings: as text, or as one JSON object per line.
+        #[structopt(long, default_value = "text", possible_values = &["text", "json"])]
+        format: String,
+    },
+
+    /// Print a program as a textual listing of its commands.
+    Disasm {
+        /// Piet source file.
+        #[structopt(name = "SOURCE", parse(from_os_str))]
+        source_path: PathBuf,
+
+        #[structopt(flatten)]
+        load_flags: LoadFlags,
+
+        #[structopt(flatten)]
+        dialect_flags: DialectFlags,
+    },
+
+    /// Draw a program as an SVG image.
+    Svg {
+        /// Piet source file.
+        #[structopt(name = "SOURCE", parse(from_os_str))]
+        source_path: PathBuf,
+
+        #[structopt(flatten)]
+        load_flags: LoadFlags,
+
+        /// The file to write, instead of stdout.
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+
+        /// How many pixels wide each codel is drawn.
+        #[structopt(long, default_value = "16")]
+        scale: u32,
+
+        /// Write the ID of each color block on it.
+        #[structopt(long)]
+        block_ids: bool,
+
+        /// Write the command that moving between two blocks executes on the
+        /// border between them.
+        #[structopt(long)]
+        commands: bool,
+    },
+}
+
+/// Load a program from a file, or from stdin if the path is `-`.
+fn load(path: &Path, options: LoadOptions) -> Program {
+    let program = if path == Path::new("-") {
+        Program::from_bytes(&read_stdin(), options)
+    } else {
+        Program::open(path, options)
+    };
+
+    program.unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        process::exit(1);
+    })
+}
+
+/// Load an image to lint from a file, or from stdin if the path is `-`. Text
+/// grids are drawn as an image first.
+fn load_image(path: &Path, options: LoadOptions) -> piet::errors::Result<DynamicImage> {
+    if path == Path::new("-") {
+        Ok(image::load_from_memory(&read_stdin())?)
+    } else if path.extension() == Some("txt".as_ref()) {
+        let codel_size = match options.codel_size {
+            CodelSize::Fixed(codel_size) => codel_size,
+            CodelSize::Auto => 1,
+        };
+        let program = Program::open(path, options)?;
+        Ok(DynamicImage::ImageRgb8(program.to_image(codel_size as u32)))
+    } else {
+        Ok(image::open(path)?)
+    }
+}
+
+fn read_stdin() -> Vec<u8> {
+    let mut bytes = Vec::new();
+    io::stdin()
+        .read_to_end(&mut bytes)
+        .expect("could not read program");
+
+    bytes
+}
+
+/// Stdin and stdout for a running program. Input is read a line at a time,
+/// only once the program needs more, and output is written as soon as the
+/// program writes it, so interactive programs work.
+#[derive(Default)]
+struct Console {
+    written: usize,
+    at_eof: bool,
+}
+
+impl Console {
+    /// Write the output that's new since the last call.
+    fn write_output(&mut self, output: &str) {
+        if output.len() > self.written {
+            let mut stdout = io::stdout();
+            stdout
+                .write_all(&output.as_bytes()[self.written..])
+                .and_then(|_| stdout.flush())
+                .expect("could not write output");
+            self.written = output.len();
+        }
+    }
+
+    /// The next line of input, or `None` at the end of it.
+    fn read_line(&mut self) -> Option<String> {
+        let mut line = String::new();
+
+        if !self.at_eof {
+            let read = io::stdin()
+                .read_line(&mut line)
+                .expect("could not read input");
+            self.at_eof = read == 0;
+        }
+
+        Some(line).filter(|_| !self.at_eof)
+    }
+
+    /// Write what the interpreter has written, and feed it input until it has
+    /// enough for its next step.
+    fn before_step(&mut self, interpreter: &mut Interpreter) {
+        self.write_output(interpreter.output());
+
+        while interpreter.needs_input() {
+            match self.read_line() {
+                Some(line) => interpreter.feed_input(&line),
+                None => break,
+            }
+        }
+    }
+}
+
+/// Write a program to a file in the text grid format if its name ends in
+/// `.txt`, or as an image otherwise.
+fn save(program: &Program, path: &Path, codel_size: u32) {
+    if path.extension() == Some("txt".as_ref()) {
+        fs::write(path, program.to_grid()).expect("could not write program");
+    } else {
+        program
+            .to_image(codel_size)
+            .save(path)
+            .expect("could not write image");
+    }
 }
 
 fn main() {
     let opt = Opt::from_args();
-    let source = image::open(opt.source_path).expect("could not open image");
 
-    let colors = source
-        .pixels()
-        .map(|(_, _, p)| Color::from_rgb(p[0], p[1], p[2]))
-        .collect();
-    let dimensions = util::map_pair(source.dimensions(), |x| x as usize);
+    if let Some(tool) = opt.tool {
+        match tool {
+            Tool::Asm {
+                source_path,
+                output,
+                codel_size,
+            } => {
+                let source = fs::read_to_string(source_path).expect("could not read source");
+                let program = assemble(&source).unwrap_or_else(|error| {
+                    eprintln!("{}", error);
+                    process::exit(1);
+                });
+
+                save(&program, &output, codel_size);
+            }
+            Tool::Text {
+                text,
+                output,
+                codel_size,
+            } => {
+                let program = print_text(&text).expect("could not generate program");
+                save(&program, &output, codel_size);
+            }
+            Tool::Compile {
+                source_path,
+                load_flags,
+                dialect_flags,
+                target,
+                output,
+            } => {
+                let program = load(&source_path, load_flags.options());
+                let cfg = Cfg::with_dialect(&program, dialect_flags.dialect());
+                let source = match target.as_str() {
+                    "c" => compile_c(&cfg),
+                    "rust" => compile_rust(&cfg),
+                    "wat" => compile_wat(&cfg),
+                    _ => unreachable!(), // Checked by `possible_values`.
+                }
+                .unwrap_or_else(|error| {
+                    eprintln!("{}", error);
+                    process::exit(1);
+                });
+
+                match output {
+                    Some(path) => fs::write(path, source).expect("could not write output"),
+                    None => print!("{}", source),
+                }
+            }
+            Tool::Check {
+                source_path,
+                load_flags,
+                dialect_flags,
+                dead_code_image,
+                scale,
+            } => {
+                let program = load(&source_path, load_flags.options());
+                let dialect = dialect_flags.dialect();
+
+                for underflow in find_underflows(&Cfg::with_dialect(&program, dialect)) {
+                    println!("warning: {}", underflow);
+                }
+
+                let dead_code = DeadCode::with_dialect(&program, dialect);
+                for line in dead_code.report().lines() {
+                    println!("note: {}", line);
+                }
+
+                if let Some(path) = dead_code_image {
+                    dead_code
+                        .image(&program, scale)
+                        .save(path)
+                        .expect("could not write dead code image");
+                }
+            }
+            Tool::Lint {
+                source_path,
+                load_flags,
+                format,
+            } => {
+                let options = load_flags.options();
+                let image = load_image(&source_path, options).unwrap_or_else(|error| {
+                    eprintln!("{}", error);
+                    process::exit(1);
+                });
+                let findings = lint(&image, options);
+
+                for finding in &findings {
+                    match format.as_str() {
+                        "json" => println!("{}", finding.to_json()),
+                        _ => println!("{}", finding),
+                    }
+                }
+
+                if findings
+                    .iter()
+                    .any(|finding| finding.severity() == Severity::Error)
+                {
+                    process::exit(1);
+                }
+            }
+            Tool::Disasm {
+                source_path,
+                load_flags,
+                dialect_flags,
+            } => {
+                let program = load(&source_path, load_flags.options());
+                let cfg = Cfg::with_dialect(&program, dialect_flags.dialect());
+                print!("{}", disassemble(&cfg));
+            }
+            Tool::Svg {
+                source_path,
+                load_flags,
+                output,
+                scale,
+                block_ids,
+                commands,
+            } => {
+                let program = load(&source_path, load_flags.options());
+                let options = SvgOptions {
+                    scale,
+                    block_ids,
+                    commands,
+                };
+                let svg = program.to_svg(&options);
+
+                match output {
+                    Some(path) => fs::write(path, svg).expect("could not write image"),
+                    None => print!("{}", svg),
+                }
+            }
+        }
+
+        return;
+    }
+
+    // This is only optional when running a subcommand.
+    let source_path = opt.source_path.as_ref().unwrap_or_else(|| {
+        clap::Error::with_description(
+            "a SOURCE is required",
+            clap::ErrorKind::MissingRequiredArgument,
+        )
+        .exit()
+    });
+
+    let program = load(source_path, opt.load_flags.options());
+    let dialect = opt.dialect_flags.dialect();
+
+    if let Some(path) = &opt.cfg {
+        fs::write(path, Cfg::with_dialect(&program, dialect).to_dot())
+            .expect("could not write control flow graph");
+    }
+
+    let wants_coverage =
+        opt.coverage || opt.coverage_image.is_some() || opt.coverage_data.is_some();
+    let wants_trace =
+        opt.trace_image.is_some() || opt.profile || opt.heatmap.is_some() || wants_coverage;
+
+    if opt.engine == "vm" {
+        if wants_trace || opt.animate.is_some() || opt.detect_loops || dialect != Dialect::NPIET {
+            clap::Error::with_description(
+                "--engine vm can't record a trace, animation, profile or coverage, detect \
+                 loops, or use a dialect other than npiet",
+                clap::ErrorKind::ArgumentConflict,
+            )
+            .exit();
+        }
+
+        let mut vm = Vm::new(&program);
+        let mut console = Console::default();
+
+        while opt.max_steps.is_none_or(|max| vm.steps() < max) {
+            console.write_output(vm.output());
+
+            while vm.needs_input() {
+                match console.read_line() {
+                    Some(line) => vm.feed_input(&line),
+                    None => break,
+                }
+            }
+
+            if !vm.step() {
+                break;
+            }
+        }
+
+        console.write_output(vm.output());
+
+        return;
+    }
 
-    let program = Program::new(dimensions, colors);
     let mut interpreter = Interpreter::new(program);
-    interpreter.run();
-    println!("{:?}", interpreter);
+    let mut console = Console::default();
+    interpreter.set_loop_detection(opt.detect_loops);
+    interpreter.set_dialect(dialect);
+
+    if let Some(path) = &opt.animate {
+        let defaults = AnimationOptions::default();
+        let options = AnimationOptions {
+            scale: opt.scale,
+            every: opt.frame_every,
+            max_steps: opt.max_steps.unwrap_or(defaults.max_steps),
+            ..defaults
+        };
+        let file = File::create(path).expect("could not create animation file");
+
+        // The animation runs the program before it really runs, so it can't
+        // be interactive, and both need all of the input.
+        let mut input = String::new();
+        io::stdin()
+            .read_to_string(&mut input)
+            .expect("could not read input");
+        interpreter.feed_input(&input);
+
+        // An error while running is reported by the run below.
+        if let Err(error) = animate(interpreter.clone(), BufWriter::new(file), &options) {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+    }
+
+    let result = if wants_trace {
+        // The reports cover the steps until an error, which is reported after
+        // them.
+        let (trace, result) = Trace::record_with(&mut interpreter, opt.max_steps, |interpreter| {
+            console.before_step(interpreter)
+        });
+        let program = interpreter.program();
+
+        if let Some(path) = opt.trace_image {
+            render_trace(program, &trace, opt.scale)
+                .save(path)
+                .expect("could not write trace image");
+        }
+
+        if opt.profile || opt.heatmap.is_some() {
+            let profile = Profile::from_trace(program, &trace);
+
+            if opt.profile {
+                eprint!("{}", profile.report(20));
+            }
+
+            if let Some(path) = opt.heatmap {
+                profile
+                    .heatmap(program, opt.scale)
+                    .save(path)
+                    .expect("could not write heatmap");
+            }
+        }
+
+        if wants_coverage {
+            let mut coverage = Coverage::new(program);
+            coverage.record_trace(&trace);
+
+            if let Some(path) = &opt.coverage_data {
+                if path.exists() {
+                    let data = fs::read_to_string(path).expect("could not read coverage data");
+                    if let Err(error) = coverage.read_data(&data) {
+                        eprintln!("{}: {}", path.display(), error);
+                        process::exit(1);
+                    }
+                }
+
+                fs::write(path, coverage.to_data()).expect("could not write coverage data");
+            }
+
+            if opt.coverage {
+                eprint!("{}", coverage.summary());
+            }
+
+            if let Some(path) = opt.coverage_image {
+                coverage
+                    .image(program, opt.scale)
+                    .save(path)
+                    .expect("could not write coverage image");
+            }
+        }
+
+        result
+    } else {
+        let mut result = Ok(());
+
+        while opt.max_steps.is_none_or(|max| interpreter.steps() < max) {
+            console.before_step(&mut interpreter);
+
+            match interpreter.step() {
+                Some(Ok(_)) => {}
+                Some(Err(error)) => {
+                    result = Err(error);
+                    break;
+                }
+                None => break,
+            }
+        }
+
+        result
+    };
+
+    // Everything written until an error is still useful.
+    console.write_output(interpreter.output());
+
+    if let Err(error) = result {
+        eprintln!("{}", error);
+        process::exit(1);
+    }
 }
@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use image::{Rgb, RgbImage};
+
+use crate::blocks::{BlockId, Blocks};
+use crate::color::Color;
+use crate::command::Command;
+use crate::program::Program;
+use crate::render::Canvas;
+use crate::trace::{Step, Trace};
+use crate::util::Coords;
+
+const UNVISITED_COLOR: Rgb<u8> = Rgb([0xd0, 0xd0, 0xd0]);
+
+/// Statistics about where a program spends its steps.
+#[derive(Debug, Clone)]
+pub struct Profile {
+    blocks: Blocks,
+    visits: Vec<u64>,
+    retries: Vec<u64>,
+    commands: HashMap<Command, u64>,
+    steps: u64,
+    slides: u64,
+    slid_codels: u64,
+    total_retries: u64,
+}
+
+impl Profile {
+    pub fn new(program: &Program) -> Self {
+        let blocks = Blocks::new(program);
+        let mut visits = vec![0; blocks.len()];
+
+        // The starting block is visited before any steps are taken.
+        if let Some(first) = visits.first_mut() {
+            *first = 1;
+        }
+
+        Profile {
+            retries: vec![0; blocks.len()],
+            visits,
+            blocks,
+            commands: HashMap::new(),
+            steps: 0,
+            slides: 0,
+            slid_codels: 0,
+            total_retries: 0,
+        }
+    }
+
+    pub fn from_trace(program: &Program, trace: &Trace) -> Self {
+        let mut profile = Profile::new(program);
+
+        for step in trace.steps() {
+            profile.record(step);
+        }
+
+        profile
+    }
+
+    /// Add a step to the statistics.
+    pub fn record(&mut self, step: &Step) {
+        self.steps += 1;
+        self.total_retries += step.retries as u64;
+
+        if let Some(from) = self.blocks.id_at(step.from) {
+            self.retries[from] += step.retries as u64;
+        }
+
+        if let Some(to) = self.blocks.id_at(step.to) {
+            self.visits[to] += 1;
+        }
+
+        if step.slide > 0 {
+            self.slides += 1;
+            self.slid_codels += step.slide as u64;
+
+            // Count the white block that was slid through as visited too.
+            let white = if step.exit == step.from && self.is_white(step.from) {
+                Some(step.from)
+            } else {
+                step.dp.offset(step.exit)
+            };
+
+            if let Some(white) = white.and_then(|coords| self.blocks.id_at(coords)) {
+                self.visits[white] += 1;
+            }
+        }
+
+        if let Some(command) = step.command {
+            *self.commands.entry(command).or_insert(0) += 1;
+        }
+    }
+
+    fn is_white(&self, coords: Coords) -> bool {
+        self.blocks
+            .id_at(coords)
+            .is_some_and(|id| self.blocks[id].color() == Color::White)
+    }
+
+    /// How many times a block was entered.
+    pub fn visits(&self, block: BlockId) -> u64 {
+        self.visits.get(block).copied().unwrap_or(0)
+    }
+
+    /// How many times a command was executed.
+    pub fn command_count(&self, command: Command) -> u64 {
+        self.commands.get(&command).copied().unwrap_or(0)
+    }
+
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// The blocks, sorted by how often they were visited, most visited first.
+    /// Blocks that were never visited are left out.
+    pub fn hot_blocks(&self) -> Vec<BlockId> {
+        let mut hot = (0..self.blocks.len())
+            .filter(|&id| self.visits[id] > 0)
+            .collect::<Vec<_>>();
+        hot.sort_by_key(|&id| std::cmp::Reverse(self.visits[id]));
+
+        hot
+    }
+
+    /// Write the statistics as a text table, listing at most `limit` blocks.
+    pub fn report(&self, limit: usize) -> String {
+        let mut report = String::new();
+
+        writeln!(report, "steps: {}", self.steps).unwrap();
+        writeln!(
+            report,
+            "white slides: {} steps, {} codels",
+            self.slides, self.slid_codels
+        )
+        .unwrap();
+        writeln!(report, "blocked retries: {}", self.total_retries).unwrap();
+
+        writeln!(report).unwrap();
+        writeln!(
+            report,
+            "{:>8} {:>8} {:>6}  {:<14} coords",
+            "visits", "retries", "size", "color"
+        )
+        .unwrap();
+        for id in self.hot_blocks().into_iter().take(limit) {
+            let block = &self.blocks[id];
+            writeln!(
+                report,
+                "{:>8} {:>8} {:>6}  {:<14} {:?}",
+                self.visits[id],
+                self.retries[id],
+                block.size(),
+                block.color().to_string(),
+                block.origin()
+            )
+            .unwrap();
+        }
+
+        let mut commands = self.commands.iter().collect::<Vec<_>>();
+        commands.sort_by_key(|&(command, &count)| (std::cmp::Reverse(count), command.mnemonic()));
+
+        writeln!(report).unwrap();
+        writeln!(report, "{:<10} {:>8}", "command", "count").unwrap();
+        for (command, count) in commands {
+            writeln!(report, "{:<10} {:>8}", command.mnemonic(), count).unwrap();
+        }
+
+        report
+    }
+
+    /// Draw a heatmap of how often each block was visited.
+    ///
+    /// Visited blocks go from blue, for the least visited, through red, to
+    /// yellow, for the most visited, on a logarithmic scale. Blocks that were
+    /// never visited are grey, and black blocks stay black.
+    pub fn heatmap(&self, program: &Program, scale: u32) -> RgbImage {
+        let mut canvas = Canvas::new(program, scale);
+        let max = self.visits.iter().copied().max().unwrap_or(0);
+
+        for (id, block) in self.blocks.iter() {
+            let color = match (block.color(), self.visits[id]) {
+                (Color::Black, _) => continue,
+                (_, 0) => UNVISITED_COLOR,
+                (_, visits) => heat((visits as f64).ln_1p() / (max as f64).ln_1p()),
+            };
+
+            for &codel in block.codels() {
+                canvas.fill_codel(codel, color);
+            }
+        }
+
+        canvas.into_image()
+    }
+}
+
+/// Map a value from 0 to 1 onto a color, from blue through red to yellow.
+fn heat(value: f64) -> Rgb<u8> {
+    let value = value.clamp(0.0, 1.0);
+
+    if value < 0.5 {
+        let t = value * 2.0;
+        Rgb([(0xff as f64 * t) as u8, 0, (0xc0 as f64 * (1.0 - t)) as u8])
+    } else {
+        let t = (value - 0.5) * 2.0;
+        Rgb([0xff, (0xff as f64 * t) as u8, 0])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::{Hue::*, Lightness::*};
+    use crate::interpreter::Interpreter;
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_profile() {
+        let lr = Color::Composite(Red, Light);
+        let nr = Color::Composite(Red, Normal);
+        let b = Color::Black;
+        let w = Color::White;
+
+        let program = Program::new((5, 1), vec![lr, w, nr, b, lr]);
+        let mut interpreter = Interpreter::new(program.clone());
+        let (trace, result) = Trace::record(&mut interpreter, Some(4));
+        result.unwrap();
+        let profile = Profile::from_trace(&program, &trace);
+
+        // The interpreter slides through white between the two red blocks,
+        // without ever executing a command.
+        assert_eq!(profile.steps(), 4);
+        assert_eq!(profile.visits(0), 3);
+        assert_eq!(profile.visits(1), 4);
+        assert_eq!(profile.visits(2), 2);
+        assert_eq!(profile.visits(4), 0);
+        assert_eq!(profile.command_count(Command::Push), 0);
+        assert_eq!(profile.hot_blocks(), vec![1, 0, 2]);
+
+        let report = profile.report(10);
+        assert!(report.contains("white slides: 4 steps, 4 codels"));
+        assert!(report.contains("light red"));
+
+        let heatmap = profile.heatmap(&program, 1);
+        assert_eq!(heatmap.get_pixel(1, 0), &Rgb([0xff, 0xff, 0x00]));
+        assert_eq!(heatmap.get_pixel(3, 0), &Rgb([0x00, 0x00, 0x00]));
+        assert_eq!(heatmap.get_pixel(4, 0), &UNVISITED_COLOR);
+    }
+
+    #[test]
+    fn test_heat() {
+        assert_eq!(heat(0.0), Rgb([0x00, 0x00, 0xc0]));
+        assert_eq!(heat(0.5), Rgb([0xff, 0x00, 0x00]));
+        assert_eq!(heat(1.0), Rgb([0xff, 0xff, 0x00]));
+    }
+}
@@ -1,9 +1,11 @@
 use std::collections::HashSet;
 
+use image::{Rgb, RgbImage};
+
 use crate::color::Color;
 use crate::util::{lift_pair, Coords};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Program {
     size: Coords,
     image: Vec<Color>,
@@ -11,16 +13,44 @@ pub struct Program {
 
 impl Program {
     pub fn new(size: Coords, image: Vec<Color>) -> Self {
-        Program {
-            size: size,
-            image: image,
-        }
+        Program { size, image }
+    }
+
+    /// The width and height of the program, in codels.
+    pub fn size(&self) -> Coords {
+        self.size
     }
 
     pub fn get(&self, coords: Coords) -> Option<Color> {
         self.coords_to_index(coords).map(|index| self.image[index])
     }
 
+    /// Iterate over all codels and their coordinates, row by row.
+    pub fn codels(&self) -> impl Iterator<Item = (Coords, Color)> + '_ {
+        self.image
+            .iter()
+            .enumerate()
+            .filter_map(move |(index, &color)| Some((self.index_to_coords(index)?, color)))
+    }
+
+    /// Draw the program as an image, with every codel a square of `codel_size`
+    /// pixels.
+    pub fn to_image(&self, codel_size: u32) -> RgbImage {
+        let codel_size = codel_size.max(1);
+        let (width, height) = self.size;
+
+        RgbImage::from_fn(
+            width as u32 * codel_size,
+            height as u32 * codel_size,
+            |x, y| {
+                let coords = ((x / codel_size) as usize, (y / codel_size) as usize);
+                let (r, g, b) = self.get(coords).unwrap_or(Color::White).to_rgb();
+
+                Rgb([r, g, b])
+            },
+        )
+    }
+
     /// Find the coordinates of a contiguous area of codels of the same color,
     /// starting from a coordinate.
     ///
@@ -108,6 +138,7 @@ impl Program {
 }
 
 #[cfg(test)]
+#[allow(clippy::deprecated_cfg_attr)]
 mod tests {
     use super::*;
 
@@ -0,0 +1,350 @@
+use std::collections::HashSet;
+
+use image::{Rgb, RgbImage};
+
+use crate::font::{self, GLYPH_HEIGHT, GLYPH_WIDTH};
+use crate::program::Program;
+use crate::trace::Trace;
+use crate::util::Coords;
+
+const ARROW_COLOR: Rgb<u8> = Rgb([0x40, 0x40, 0x40]);
+const EXIT_COLOR: Rgb<u8> = Rgb([0x80, 0x80, 0x80]);
+const LABEL_COLOR: Rgb<u8> = Rgb([0x00, 0x00, 0x00]);
+const LABEL_BACKGROUND: Rgb<u8> = Rgb([0xff, 0xff, 0xff]);
+const HALT_COLOR: Rgb<u8> = Rgb([0xff, 0x80, 0x00]);
+const MARGIN_COLOR: Rgb<u8> = Rgb([0xe0, 0xe0, 0xe0]);
+
+/// An image of a program, scaled up so every codel is a square of `scale`
+/// pixels, that can be drawn on.
+pub(crate) struct Canvas {
+    image: RgbImage,
+    scale: u32,
+}
+
+impl Canvas {
+    pub fn new(program: &Program, scale: u32) -> Self {
+        Canvas::with_margin(program, scale, 0, 0)
+    }
+
+    /// Create a canvas with an empty margin of `margin` pixels to the right of
+    /// the program, which is at least `min_height` pixels high.
+    pub fn with_margin(program: &Program, scale: u32, margin: u32, min_height: u32) -> Self {
+        let scale = scale.max(1);
+        let (width, height) = program.size();
+        let mut canvas = Canvas {
+            image: RgbImage::from_pixel(
+                width as u32 * scale + margin,
+                (height as u32 * scale).max(min_height),
+                MARGIN_COLOR,
+            ),
+            scale,
+        };
+
+        for (coords, color) in program.codels() {
+            let (r, g, b) = color.to_rgb();
+            canvas.fill_codel(coords, Rgb([r, g, b]));
+        }
+
+        canvas
+    }
+
+    pub fn scale(&self) -> u32 {
+        self.scale
+    }
+
+    pub fn into_image(self) -> RgbImage {
+        self.image
+    }
+
+    /// The pixel at the center of a codel.
+    pub fn center(&self, coords: Coords) -> (i64, i64) {
+        let (x, y) = coords;
+        let half = (self.scale / 2) as i64;
+
+        (
+            x as i64 * self.scale as i64 + half,
+            y as i64 * self.scale as i64 + half,
+        )
+    }
+
+    pub fn put(&mut self, x: i64, y: i64, color: Rgb<u8>) {
+        if x >= 0 && y >= 0 && x < self.image.width() as i64 && y < self.image.height() as i64 {
+            self.image.put_pixel(x as u32, y as u32, color);
+        }
+    }
+
+    pub fn fill_rect(&mut self, x: i64, y: i64, width: u32, height: u32, color: Rgb<u8>) {
+        for dy in 0..height as i64 {
+            for dx in 0..width as i64 {
+                self.put(x + dx, y + dy, color);
+            }
+        }
+    }
+
+    pub fn fill_codel(&mut self, coords: Coords, color: Rgb<u8>) {
+        let (x, y) = coords;
+        let scale = self.scale;
+
+        self.fill_rect(
+            (x as u32 * scale) as i64,
+            (y as u32 * scale) as i64,
+            scale,
+            scale,
+            color,
+        );
+    }
+
+    /// Draw diagonal stripes over a codel. The stripes line up across
+    /// neighboring codels.
+    pub fn hatch_codel(&mut self, coords: Coords, color: Rgb<u8>) {
+        let (x, y) = coords;
+        let scale = self.scale as i64;
+        let spacing = (scale / 2).max(2);
+        let (left, top) = (x as i64 * scale, y as i64 * scale);
+
+        for py in top..top + scale {
+            for px in left..left + scale {
+                if (px + py) % spacing == 0 {
+                    self.put(px, py, color);
+                }
+            }
+        }
+    }
+
+    /// Draw a line of `thickness` pixels along the outside edges of a set of
+    /// codels.
+    pub fn outline(&mut self, codels: &HashSet<Coords>, thickness: u32, color: Rgb<u8>) {
+        let scale = self.scale as i64;
+        let thickness = thickness.clamp(1, self.scale);
+        let inset = scale - thickness as i64;
+
+        for &(x, y) in codels {
+            let (left, top) = (x as i64 * scale, y as i64 * scale);
+            let has = |dx: i64, dy: i64| {
+                let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+                nx >= 0 && ny >= 0 && codels.contains(&(nx as usize, ny as usize))
+            };
+
+            if !has(0, -1) {
+                self.fill_rect(left, top, self.scale, thickness, color);
+            }
+            if !has(0, 1) {
+                self.fill_rect(left, top + inset, self.scale, thickness, color);
+            }
+            if !has(-1, 0) {
+                self.fill_rect(left, top, thickness, self.scale, color);
+            }
+            if !has(1, 0) {
+                self.fill_rect(left + inset, top, thickness, self.scale, color);
+            }
+        }
+    }
+
+    /// Fill a square of `size` pixels wide, centered on a pixel.
+    pub fn mark(&mut self, center: (i64, i64), size: u32, color: Rgb<u8>) {
+        let (x, y) = center;
+        let half = (size / 2) as i64;
+
+        self.fill_rect(x - half, y - half, size.max(1), size.max(1), color);
+    }
+
+    /// Fill a square in the middle of a codel, `size` pixels wide.
+    pub fn mark_codel(&mut self, coords: Coords, size: u32, color: Rgb<u8>) {
+        self.mark(self.center(coords), size, color);
+    }
+
+    /// Draw a line between two pixels, using Bresenham's algorithm.
+    pub fn draw_line(&mut self, from: (i64, i64), to: (i64, i64), color: Rgb<u8>) {
+        let (mut x, mut y) = from;
+        let (dx, dy) = ((to.0 - x).abs(), -(to.1 - y).abs());
+        let (sx, sy) = ((to.0 - x).signum(), (to.1 - y).signum());
+        let mut error = dx + dy;
+
+        loop {
+            self.put(x, y, color);
+
+            if (x, y) == to {
+                break;
+            }
+
+            let doubled = 2 * error;
+            if doubled >= dy {
+                error += dy;
+                x += sx;
+            }
+            if doubled <= dx {
+                error += dx;
+                y += sy;
+            }
+        }
+    }
+
+    /// Draw a line with an arrowhead at the end.
+    pub fn draw_arrow(&mut self, from: (i64, i64), to: (i64, i64), color: Rgb<u8>) {
+        self.draw_line(from, to, color);
+
+        let (dx, dy) = ((to.0 - from.0) as f64, (to.1 - from.1) as f64);
+        let length = (dx * dx + dy * dy).sqrt();
+        if length == 0.0 {
+            return;
+        }
+
+        let head = (self.scale as f64 / 3.0).max(2.0);
+        let (ux, uy) = (dx / length, dy / length);
+
+        for &side in &[-1.0, 1.0] {
+            // Rotate the reversed direction by 30 degrees either way.
+            let (sin, cos) = (0.5 * side, 0.75f64.sqrt());
+            let (rx, ry) = (-ux * cos + uy * sin, -ux * -sin - uy * cos);
+            let end = (
+                to.0 + (rx * head).round() as i64,
+                to.1 + (ry * head).round() as i64,
+            );
+            self.draw_line(to, end, color);
+        }
+    }
+
+    /// Draw text, with its top left corner at a pixel. Each pixel of the font
+    /// is drawn as a square of `size` pixels.
+    pub fn draw_text(&mut self, x: i64, y: i64, text: &str, size: u32, color: Rgb<u8>) {
+        let size = size.max(1);
+
+        for (index, c) in text.chars().enumerate() {
+            let left = x + (index as u32 * (GLYPH_WIDTH + 1) * size) as i64;
+
+            for (row, bits) in font::glyph(c).iter().enumerate() {
+                for column in 0..GLYPH_WIDTH {
+                    if bits & (1 << (GLYPH_WIDTH - 1 - column)) != 0 {
+                        self.fill_rect(
+                            left + (column * size) as i64,
+                            y + (row as u32 * size) as i64,
+                            size,
+                            size,
+                            color,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    /// Draw text on a background, centered on a pixel.
+    pub fn draw_label(&mut self, center: (i64, i64), text: &str, size: u32) {
+        let size = size.max(1);
+        let width = text.chars().count() as u32 * (GLYPH_WIDTH + 1) * size + size;
+        let height = (GLYPH_HEIGHT + 2) * size;
+        let (x, y) = (
+            center.0 - (width / 2) as i64,
+            center.1 - (height / 2) as i64,
+        );
+
+        self.fill_rect(x, y, width, height, LABEL_BACKGROUND);
+        self.draw_text(x + size as i64, y + size as i64, text, size, LABEL_COLOR);
+    }
+}
+
+/// Draw the path that the interpreter took through a program.
+///
+/// The image is scaled up so every codel is `scale` pixels wide. Every
+/// transition between color blocks is drawn as an arrow from the codel that
+/// was left, to the codel that was entered, labeled with the command it
+/// executed. If the program halted, the codel it halted at is marked.
+pub fn render_trace(program: &Program, trace: &Trace, scale: u32) -> RgbImage {
+    let mut canvas = Canvas::new(program, scale);
+    let scale = canvas.scale();
+    let text_size = (scale / 16).max(1);
+
+    let mut seen = HashSet::new();
+    let transitions = trace
+        .steps()
+        .iter()
+        .filter(|step| seen.insert((step.exit, step.to)))
+        .collect::<Vec<_>>();
+
+    for step in &transitions {
+        let (from, to) = (canvas.center(step.exit), canvas.center(step.to));
+        canvas.draw_arrow(from, to, ARROW_COLOR);
+    }
+
+    for step in &transitions {
+        canvas.mark_codel(step.exit, scale / 3, EXIT_COLOR);
+    }
+
+    for step in &transitions {
+        if let Some(command) = step.command {
+            let (from, to) = (canvas.center(step.exit), canvas.center(step.to));
+            let middle = ((from.0 + to.0) / 2, (from.1 + to.1) / 2);
+            canvas.draw_label(middle, command.mnemonic(), text_size);
+        }
+    }
+
+    if trace.halted() {
+        let end = trace.end();
+        let (x, y) = canvas.center(end);
+        let half = (scale / 2) as i64;
+
+        canvas.draw_line(
+            (x - half, y - half),
+            (x + half - 1, y + half - 1),
+            HALT_COLOR,
+        );
+        canvas.draw_line(
+            (x - half, y + half - 1),
+            (x + half - 1, y - half),
+            HALT_COLOR,
+        );
+        canvas.mark_codel(end, scale / 3, HALT_COLOR);
+    }
+
+    canvas.into_image()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::{Color, Hue::*, Lightness::*};
+    use crate::interpreter::Interpreter;
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_render_trace() {
+        let lr = Color::Composite(Red, Light);
+        let nr = Color::Composite(Red, Normal);
+        let dm = Color::Composite(Magenta, Dark);
+        let b = Color::Black;
+
+        let program = Program::new((5, 2), vec![
+            lr, lr, nr, dm, b,
+            b,  b,  dm, dm, b,
+        ]);
+
+        let mut interpreter = Interpreter::new(program.clone());
+        let (trace, result) = Trace::record(&mut interpreter, None);
+        result.unwrap();
+        let image = render_trace(&program, &trace, 20);
+
+        assert_eq!(image.dimensions(), (100, 40));
+
+        // Codels that weren't drawn over keep their color.
+        assert_eq!(image.get_pixel(1, 1), &Rgb([0xff, 0xc0, 0xc0]));
+        assert_eq!(image.get_pixel(81, 1), &Rgb([0x00, 0x00, 0x00]));
+
+        // The codel where the program halted is marked.
+        assert_eq!(image.get_pixel(70, 10), &HALT_COLOR);
+
+        // The exit codel of the first block has a marker.
+        assert_eq!(image.get_pixel(30, 10), &EXIT_COLOR);
+    }
+
+    #[test]
+    fn test_canvas_draw_line() {
+        let mut canvas = Canvas::new(&Program::new((1, 1), vec![Color::White]), 5);
+        canvas.draw_line((0, 0), (4, 2), LABEL_COLOR);
+        let image = canvas.into_image();
+
+        assert_eq!(image.get_pixel(0, 0), &LABEL_COLOR);
+        assert_eq!(image.get_pixel(2, 1), &LABEL_COLOR);
+        assert_eq!(image.get_pixel(4, 2), &LABEL_COLOR);
+        assert_eq!(image.get_pixel(0, 4), &LABEL_BACKGROUND);
+    }
+}
@@ -1,7 +1,7 @@
 use std::ops::{Deref, DerefMut};
 
 /// A wrapper type around a Vec<i64> to represent Piet's stack.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct Stack(Vec<i64>);
 
 impl Stack {
@@ -0,0 +1,261 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use crate::blocks::{Block, Blocks};
+use crate::color::{Color, Lightness};
+use crate::command::Command;
+use crate::coverage::ALL_DIRECTIONS;
+use crate::interpreter::DirectionPointer;
+use crate::program::Program;
+
+/// Settings for `Program::to_svg`.
+#[derive(Debug, Clone)]
+pub struct SvgOptions {
+    /// How many pixels wide each codel is drawn, if the image isn't scaled.
+    pub scale: u32,
+    /// Write the ID of each block on its first codel.
+    pub block_ids: bool,
+    /// Write the command that moving from one block to the next executes on
+    /// the border between them.
+    pub commands: bool,
+}
+
+impl Default for SvgOptions {
+    fn default() -> Self {
+        SvgOptions {
+            scale: 16,
+            block_ids: false,
+            commands: false,
+        }
+    }
+}
+
+impl Program {
+    /// Draw the program as an SVG image, with a single shape for each color
+    /// block.
+    ///
+    /// Positions in the image are in codels, so the image stays sharp at any
+    /// size.
+    pub fn to_svg(&self, options: &SvgOptions) -> String {
+        let (width, height) = self.size();
+        let blocks = Blocks::new(self);
+        let mut svg = String::new();
+
+        writeln!(
+            svg,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" \
+             viewBox=\"0 0 {} {}\">",
+            width as u32 * options.scale,
+            height as u32 * options.scale,
+            width,
+            height
+        )
+        .unwrap();
+
+        writeln!(svg, "  <g shape-rendering=\"crispEdges\">").unwrap();
+        for (id, block) in blocks.iter() {
+            let (r, g, b) = block.color().to_rgb();
+            writeln!(
+                svg,
+                "    <path id=\"block-{}\" fill=\"#{:02x}{:02x}{:02x}\" d=\"{}\"/>",
+                id,
+                r,
+                g,
+                b,
+                outline(block)
+            )
+            .unwrap();
+        }
+        writeln!(svg, "  </g>").unwrap();
+
+        if options.block_ids {
+            writeln!(
+                svg,
+                "  <g font-family=\"monospace\" font-size=\"0.5\" text-anchor=\"middle\" \
+                 dominant-baseline=\"central\">"
+            )
+            .unwrap();
+
+            for (id, block) in blocks.iter() {
+                let (x, y) = block.origin();
+                let fill = match block.color() {
+                    Color::Black | Color::Composite(_, Lightness::Dark) => "white",
+                    _ => "black",
+                };
+
+                writeln!(
+                    svg,
+                    "    <text x=\"{}.5\" y=\"{}.5\" fill=\"{}\">{}</text>",
+                    x, y, fill, id
+                )
+                .unwrap();
+            }
+
+            writeln!(svg, "  </g>").unwrap();
+        }
+
+        if options.commands {
+            writeln!(
+                svg,
+                "  <g font-family=\"monospace\" font-size=\"0.3\" text-anchor=\"middle\" \
+                 dominant-baseline=\"central\" fill=\"black\" stroke=\"white\" \
+                 stroke-width=\"0.08\" paint-order=\"stroke\">"
+            )
+            .unwrap();
+
+            for ((y, x), label) in commands(self, &blocks) {
+                writeln!(
+                    svg,
+                    "    <text x=\"{}\" y=\"{}\">{}</text>",
+                    x as f64 / 4.0,
+                    y as f64 / 4.0,
+                    label
+                )
+                .unwrap();
+            }
+
+            writeln!(svg, "  </g>").unwrap();
+        }
+
+        writeln!(svg, "</svg>").unwrap();
+
+        svg
+    }
+}
+
+/// The path data for a block, as rectangles that each cover as many rows of
+/// the same horizontal run of codels as possible.
+fn outline(block: &Block) -> String {
+    // Each rectangle is (x, y, width, height).
+    let mut rectangles: Vec<(usize, usize, usize, usize)> = Vec::new();
+    // The rectangles that a run can extend, by the run's start, end and row.
+    let mut open: HashMap<_, usize> = HashMap::new();
+    let mut codels = block.codels().iter().peekable();
+
+    while let Some(&(start, y)) = codels.next() {
+        let mut end = start + 1;
+        while codels.peek() == Some(&&(end, y)) {
+            codels.next();
+            end += 1;
+        }
+
+        match open.get(&(start, end, y)) {
+            Some(&index) => {
+                rectangles[index].3 += 1;
+                open.insert((start, end, y + 1), index);
+            }
+            None => {
+                rectangles.push((start, y, end - start, 1));
+                open.insert((start, end, y + 1), rectangles.len() - 1);
+            }
+        }
+    }
+
+    let paths = rectangles
+        .iter()
+        .map(|(x, y, width, height)| format!("M{} {}h{}v{}h-{}z", x, y, width, height, width))
+        .collect::<Vec<_>>();
+
+    paths.join(" ")
+}
+
+/// A label for each way to move from one colored block to a neighboring one,
+/// like "→push", and where to write it, in quarter codels.
+///
+/// Labels are written on the border between the two codels, a little above
+/// it for moving right or down, and a little below it for moving left or up,
+/// so the labels for moving both ways across a border don't overlap.
+fn commands(program: &Program, blocks: &Blocks) -> BTreeMap<(usize, usize), String> {
+    let mut commands = BTreeMap::new();
+
+    for (_, block) in blocks.iter() {
+        for &(dp, cc) in &ALL_DIRECTIONS {
+            let exit = block.exit(dp, cc);
+            let next = match dp.offset(exit).and_then(|next| program.get(next)) {
+                Some(next) => next,
+                None => continue,
+            };
+
+            let transition = match Color::transition(block.color(), next) {
+                Some(transition) => transition,
+                None => continue,
+            };
+
+            let (x, y) = (exit.0 * 4 + 2, exit.1 * 4 + 2);
+            let (at, arrow) = match dp {
+                DirectionPointer::Up => ((x, y - 1), '↑'),
+                DirectionPointer::Right => ((x + 2, y - 1), '→'),
+                DirectionPointer::Down => ((x, y + 1), '↓'),
+                DirectionPointer::Left => ((x - 2, y + 1), '←'),
+            };
+            let command = Command::from_transition(transition);
+
+            commands.insert((at.1, at.0), format!("{}{}", arrow, command.mnemonic()));
+        }
+    }
+
+    commands
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::{Hue::*, Lightness::*};
+
+    const B: Color = Color::Black;
+    const LR: Color = Color::Composite(Red, Light);
+    const NR: Color = Color::Composite(Red, Normal);
+
+    #[rustfmt::skip]
+    fn program() -> Program {
+        Program::new((3, 3), vec![
+            LR, LR, NR,
+            LR, B,  B,
+            LR, LR, B,
+        ])
+    }
+
+    #[test]
+    fn test_outline() {
+        let program = program();
+        let blocks = Blocks::new(&program);
+        let light_red = &blocks[blocks.id_at((0, 0)).unwrap()];
+
+        assert_eq!(outline(light_red), "M0 0h2v1h-2z M0 1h1v1h-1z M0 2h2v1h-2z");
+
+        // Rows with the same run are merged.
+        let program = Program::new((2, 3), vec![LR; 6]);
+        let blocks = Blocks::new(&program);
+        assert_eq!(outline(&blocks[0]), "M0 0h2v3h-2z");
+    }
+
+    #[test]
+    fn test_program_to_svg() {
+        let program = program();
+        let plain = program.to_svg(&SvgOptions::default());
+
+        assert!(plain.starts_with(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"48\" height=\"48\" \
+             viewBox=\"0 0 3 3\">\n"
+        ));
+        assert!(plain.ends_with("</svg>\n"));
+        assert_eq!(plain.matches("<path ").count(), Blocks::new(&program).len());
+        assert!(plain.contains("fill=\"#ffc0c0\""));
+        assert!(!plain.contains("<text"));
+
+        let options = SvgOptions {
+            block_ids: true,
+            commands: true,
+            ..SvgOptions::default()
+        };
+        let annotated = program.to_svg(&options);
+        let nr = Blocks::new(&program).id_at((2, 0)).unwrap();
+
+        assert!(annotated.contains(&format!(">{}</text>", nr)));
+        // Light red to normal red pushes, and back pops, across the same
+        // border.
+        assert!(annotated.contains("<text x=\"2\" y=\"0.25\">→push</text>"));
+        assert!(annotated.contains("<text x=\"2\" y=\"0.75\">←pop</text>"));
+        assert_eq!(annotated.matches("push<").count(), 1);
+    }
+}
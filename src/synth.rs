@@ -0,0 +1,411 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::rc::Rc;
+
+use crate::command::Command;
+
+/// Numbers from `-TABLE_LIMIT` to `TABLE_LIMIT` are synthesized optimally.
+const TABLE_LIMIT: i64 = 512;
+
+/// The small factors tried when splitting numbers outside the table.
+const SPLIT_LIMIT: i64 = 16;
+
+/// What makes a sequence of commands expensive.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Cost {
+    /// Every command costs one step. `Push` can push at most `max_push`, which
+    /// is always at least 1.
+    Steps { max_push: u64 },
+    /// Every codel costs one: pushing `n` takes a block of `n` codels, and any
+    /// other command a single codel.
+    Area,
+}
+
+/// How to calculate a number.
+#[derive(Debug)]
+enum Expr {
+    Push(u64),
+    /// Calculate both values, then combine them.
+    Binary(Command, Rc<Expr>, Rc<Expr>),
+    /// Calculate a value, duplicate it, then combine the two copies.
+    Dup(Command, Rc<Expr>),
+}
+
+type Best = (u64, Rc<Expr>);
+
+/// Finds cheap sequences of `Push`, `Duplicate`, `Add`, `Subtract`,
+/// `Multiply`, `Divide` and `Mod` that push a number.
+///
+/// Small numbers are found with a shortest path search over every way of
+/// combining two smaller results, so they're optimal. Bigger numbers are split
+/// into `a * b + c` or `a * b - c` in several ways, remembering the best way
+/// to calculate every part.
+#[derive(Debug)]
+pub struct Synthesizer {
+    table: Vec<Best>,
+    memo: HashMap<i64, Best>,
+}
+
+impl Synthesizer {
+    pub fn new(cost: Cost) -> Self {
+        // Without pushing at least 1, nothing can be calculated at all.
+        let cost = match cost {
+            Cost::Steps { max_push } => Cost::Steps {
+                max_push: max_push.max(1),
+            },
+            Cost::Area => Cost::Area,
+        };
+
+        Synthesizer {
+            table: table(cost),
+            memo: HashMap::new(),
+        }
+    }
+
+    /// The cost of the cheapest sequence found for `value`.
+    pub fn cost(&mut self, value: i64) -> u64 {
+        self.best(value).0
+    }
+
+    /// The cheapest sequence found for `value`. Each command comes with the
+    /// size of the block it's executed from, which is the pushed value for
+    /// `Push`, and 1 for everything else.
+    pub fn synthesize(&mut self, value: i64) -> Vec<(Command, usize)> {
+        let (_, expr) = self.best(value);
+        let mut commands = Vec::new();
+        flatten(&expr, &mut commands);
+
+        commands
+    }
+
+    fn best(&mut self, value: i64) -> Best {
+        if (-TABLE_LIMIT..=TABLE_LIMIT).contains(&value) {
+            let (cost, expr) = &self.table[index(value)];
+            return (*cost, expr.clone());
+        }
+
+        if let Some((cost, expr)) = self.memo.get(&value) {
+            return (*cost, expr.clone());
+        }
+
+        let best = if value > 0 {
+            self.split(value)
+        } else {
+            self.negate(value)
+        };
+
+        self.memo.insert(value, best.clone());
+        best
+    }
+
+    /// Calculate a big positive number from smaller ones. Every part is at
+    /// most about half of `value`, so this always terminates.
+    fn split(&mut self, value: i64) -> Best {
+        let mut candidates = Vec::new();
+
+        if value % 2 == 0 {
+            candidates.push(self.dup(Command::Add, value / 2));
+        }
+
+        let root = (value as f64).sqrt() as i64;
+        if root * root == value {
+            candidates.push(self.dup(Command::Multiply, root));
+        }
+
+        let small = (2..=SPLIT_LIMIT)
+            .filter(|&divisor| divisor == 2 || value % divisor == 0)
+            .map(|divisor| (divisor, false));
+        let near_root = (root - 1..=root + 1).map(|divisor| (divisor, true));
+
+        // Small divisors other than 2 are only tried when they're factors, and
+        // quotients are only rounded up near the root. Trying everything would
+        // make the number of parts grow too quickly.
+        for (divisor, round_up) in small.chain(near_root).filter(|&(divisor, _)| divisor >= 2) {
+            let (quotient, remainder) = (value / divisor, value % divisor);
+
+            let product = self.product(quotient, divisor);
+            candidates.push(if remainder == 0 {
+                product
+            } else {
+                let remainder = self.best(remainder);
+                binary(Command::Add, product, remainder)
+            });
+
+            if remainder != 0 && round_up {
+                let product = self.product(quotient + 1, divisor);
+                let deficit = self.best(divisor - remainder);
+                candidates.push(binary(Command::Subtract, product, deficit));
+            }
+        }
+
+        candidates
+            .into_iter()
+            .min_by_key(|(cost, _)| *cost)
+            .unwrap()
+    }
+
+    /// Calculate a big negative number, by subtracting from a small number.
+    fn negate(&mut self, value: i64) -> Best {
+        let mut candidates = Vec::new();
+
+        for start in 0..=1i64 {
+            if let Some(magnitude) = start.checked_sub(value) {
+                let start = self.best(start);
+                let magnitude = self.best(magnitude);
+                candidates.push(binary(Command::Subtract, start, magnitude));
+            }
+        }
+
+        // Only `i64::MIN` is left out above.
+        if candidates.is_empty() {
+            candidates.push(self.dup(Command::Add, value / 2));
+        }
+
+        candidates
+            .into_iter()
+            .min_by_key(|(cost, _)| *cost)
+            .unwrap()
+    }
+
+    fn product(&mut self, a: i64, b: i64) -> Best {
+        if a == b {
+            self.dup(Command::Multiply, a)
+        } else {
+            let (a, b) = (self.best(a), self.best(b));
+            binary(Command::Multiply, a, b)
+        }
+    }
+
+    fn dup(&mut self, command: Command, value: i64) -> Best {
+        let (cost, expr) = self.best(value);
+
+        (cost + 2, Rc::new(Expr::Dup(command, expr)))
+    }
+}
+
+fn binary(command: Command, (a_cost, a): Best, (b_cost, b): Best) -> Best {
+    (a_cost + b_cost + 1, Rc::new(Expr::Binary(command, a, b)))
+}
+
+/// How a number in the table is calculated from cheaper numbers in the table.
+#[derive(Debug, Copy, Clone)]
+enum Recipe {
+    Push(u64),
+    Binary(Command, i64, i64),
+    Dup(Command, i64),
+}
+
+/// Find the best way to calculate every number in the table, cheapest first.
+fn table(cost: Cost) -> Vec<Best> {
+    let size = (2 * TABLE_LIMIT + 1) as usize;
+    let mut best: Vec<Option<(u64, Recipe)>> = vec![None; size];
+    let mut done = vec![false; size];
+    let mut finished = Vec::new();
+    let mut queue = BinaryHeap::new();
+
+    let max_push = match cost {
+        Cost::Steps { max_push } => max_push.min(TABLE_LIMIT as u64),
+        Cost::Area => TABLE_LIMIT as u64,
+    };
+
+    let offer = |best: &mut Vec<Option<(u64, Recipe)>>,
+                 queue: &mut BinaryHeap<_>,
+                 value: i64,
+                 cost: u64,
+                 recipe: Recipe| {
+        if (-TABLE_LIMIT..=TABLE_LIMIT).contains(&value) {
+            let position = index(value);
+
+            if best[position].is_none_or(|(best, _)| cost < best) {
+                best[position] = Some((cost, recipe));
+                queue.push(Reverse((cost, value)));
+            }
+        }
+    };
+
+    for value in 1..=max_push {
+        let push_cost = match cost {
+            Cost::Steps { .. } => 1,
+            Cost::Area => value,
+        };
+
+        offer(
+            &mut best,
+            &mut queue,
+            value as i64,
+            push_cost,
+            Recipe::Push(value),
+        );
+    }
+
+    while let Some(Reverse((_, value))) = queue.pop() {
+        let position = index(value);
+        if done[position] {
+            continue;
+        }
+        done[position] = true;
+
+        let (current, _) = best[position].unwrap();
+
+        for (command, result) in combine(value, value) {
+            offer(
+                &mut best,
+                &mut queue,
+                result,
+                current + 2,
+                Recipe::Dup(command, value),
+            );
+        }
+
+        for &other in &finished {
+            let (other_cost, _) = best[index(other)].unwrap();
+            let cost = current + other_cost + 1;
+
+            for &(a, b) in &[(value, other), (other, value)] {
+                for (command, result) in combine(a, b) {
+                    offer(
+                        &mut best,
+                        &mut queue,
+                        result,
+                        cost,
+                        Recipe::Binary(command, a, b),
+                    );
+                }
+            }
+        }
+
+        finished.push(value);
+    }
+
+    // Every number can be reached, so none of these are `None`. The parts of
+    // a number always cost less than it does, so building the expressions
+    // cheapest first means the parts are always ready.
+    let best: Vec<(u64, Recipe)> = best.into_iter().map(Option::unwrap).collect();
+    let mut exprs: Vec<Option<Rc<Expr>>> = vec![None; size];
+    let mut order: Vec<usize> = (0..size).collect();
+    order.sort_by_key(|&position| best[position].0);
+
+    for position in order {
+        let part = |value: i64| exprs[index(value)].clone().unwrap();
+
+        let expr = match best[position].1 {
+            Recipe::Push(value) => Expr::Push(value),
+            Recipe::Binary(command, a, b) => Expr::Binary(command, part(a), part(b)),
+            Recipe::Dup(command, a) => Expr::Dup(command, part(a)),
+        };
+        exprs[position] = Some(Rc::new(expr));
+    }
+
+    best.iter()
+        .zip(exprs)
+        .map(|(&(cost, _), expr)| (cost, expr.unwrap()))
+        .collect()
+}
+
+/// The position of a number in the table.
+fn index(value: i64) -> usize {
+    (value + TABLE_LIMIT) as usize
+}
+
+/// Every result of combining `a` and `b`, with `b` on top of the stack. Only
+/// positive numbers are divided, since dialects disagree about rounding.
+fn combine(a: i64, b: i64) -> Vec<(Command, i64)> {
+    let mut results = vec![
+        (Command::Add, a + b),
+        (Command::Subtract, a - b),
+        (Command::Multiply, a * b),
+    ];
+
+    if a > 0 && b > 0 {
+        results.push((Command::Divide, a / b));
+        results.push((Command::Mod, a % b));
+    }
+
+    results
+}
+
+fn flatten(expr: &Expr, commands: &mut Vec<(Command, usize)>) {
+    match expr {
+        Expr::Push(value) => commands.push((Command::Push, *value as usize)),
+        Expr::Binary(command, a, b) => {
+            flatten(a, commands);
+            flatten(b, commands);
+            commands.push((*command, 1));
+        }
+        Expr::Dup(command, a) => {
+            flatten(a, commands);
+            commands.push((Command::Duplicate, 1));
+            commands.push((*command, 1));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stack::Stack;
+
+    /// Run a sequence on an empty stack, the way the interpreter would.
+    fn evaluate(commands: &[(Command, usize)]) -> Vec<i64> {
+        let mut stack = Stack::default();
+
+        for &(command, size) in commands {
+            match command {
+                Command::Push => stack.push(size as i64),
+                Command::Duplicate => {
+                    let top = *stack.peek().unwrap();
+                    stack.push(top);
+                }
+                Command::Add => stack.fold_top(|b, a| a.wrapping_add(b)),
+                Command::Subtract => stack.fold_top(|b, a| a.wrapping_sub(b)),
+                Command::Multiply => stack.fold_top(|b, a| a.wrapping_mul(b)),
+                Command::Divide => stack.fold_top(|b, a| a / b),
+                Command::Mod => stack.fold_top(|b, a| a % b),
+                _ => unreachable!(),
+            }
+        }
+
+        stack.to_vec()
+    }
+
+    fn cost(commands: &[(Command, usize)], model: Cost) -> u64 {
+        match model {
+            Cost::Steps { .. } => commands.len() as u64,
+            Cost::Area => commands.iter().map(|&(_, size)| size as u64).sum(),
+        }
+    }
+
+    #[test]
+    fn test_synthesize() {
+        for &model in &[Cost::Area, Cost::Steps { max_push: 5 }] {
+            let mut synthesizer = Synthesizer::new(model);
+
+            let values = (-600..600).chain(vec![1_000_003, -65_536, 1 << 40, i64::MAX, i64::MIN]);
+
+            for value in values {
+                let commands = synthesizer.synthesize(value);
+
+                assert_eq!(evaluate(&commands), vec![value]);
+                assert_eq!(cost(&commands, model), synthesizer.cost(value));
+            }
+        }
+    }
+
+    #[test]
+    fn test_synthesize_optimal() {
+        let mut steps = Synthesizer::new(Cost::Steps { max_push: 1 });
+        assert_eq!(steps.cost(1), 1);
+        assert_eq!(steps.cost(2), 3);
+        assert_eq!(steps.cost(0), 3);
+        assert_eq!(steps.cost(4), 5);
+
+        let mut unpushable = Synthesizer::new(Cost::Steps { max_push: 0 });
+        assert_eq!(unpushable.cost(4), 5);
+        assert_eq!(unpushable.synthesize(1), vec![(Command::Push, 1)]);
+
+        let mut area = Synthesizer::new(Cost::Area);
+        assert_eq!(area.synthesize(3), vec![(Command::Push, 3)]);
+        assert_eq!(area.cost(72), 9);
+        assert!((1..=100).all(|value| area.cost(value) <= value as u64));
+    }
+}
@@ -0,0 +1,152 @@
+use crate::command::Command;
+use crate::errors::*;
+use crate::interpreter::{CodelChooser, DirectionPointer, Interpreter};
+use crate::util::Coords;
+
+/// A description of a single step of the interpreter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    /// The codel the interpreter was at before the step.
+    pub from: Coords,
+    /// The codel on the edge of the color block that the interpreter left
+    /// from. When starting on a white codel, this is the same as `from`.
+    pub exit: Coords,
+    /// The codel the interpreter moved to.
+    pub to: Coords,
+    /// The direction pointer when leaving the color block.
+    pub dp: DirectionPointer,
+    /// The codel chooser when leaving the color block.
+    pub cc: CodelChooser,
+    /// The command that was executed, if any. Moving through white never
+    /// executes a command.
+    pub command: Option<Command>,
+    /// The direction pointer after executing the command.
+    pub next_dp: DirectionPointer,
+    /// The codel chooser after executing the command.
+    pub next_cc: CodelChooser,
+    /// How many times the interpreter was blocked, and had to change the DP
+    /// or CC, before it could move.
+    pub retries: u32,
+    /// How many white codels were passed through.
+    pub slide: usize,
+}
+
+/// A record of every step taken while running a program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trace {
+    steps: Vec<Step>,
+    end: Coords,
+    halted: bool,
+}
+
+impl Trace {
+    /// Run an interpreter, recording every step.
+    ///
+    /// If `max_steps` is given, this stops after that many steps, even if the
+    /// program hasn't halted. If a step fails, this stops there, and returns
+    /// the error along with the steps until then.
+    pub fn record(interpreter: &mut Interpreter, max_steps: Option<u64>) -> (Self, Result<()>) {
+        Trace::record_with(interpreter, max_steps, |_| {})
+    }
+
+    /// Like `record`, but calls `before_step` before every step, which can
+    /// feed more input, or write out the output so far.
+    pub fn record_with<F>(
+        interpreter: &mut Interpreter,
+        max_steps: Option<u64>,
+        mut before_step: F,
+    ) -> (Self, Result<()>)
+    where
+        F: FnMut(&mut Interpreter),
+    {
+        let mut steps = Vec::new();
+        let mut result = Ok(());
+
+        while max_steps.is_none_or(|max| (steps.len() as u64) < max) {
+            before_step(interpreter);
+
+            match interpreter.step() {
+                Some(Ok(step)) => steps.push(step),
+                Some(Err(error)) => {
+                    result = Err(error);
+                    break;
+                }
+                None => break,
+            }
+        }
+
+        let trace = Trace {
+            steps,
+            end: interpreter.position(),
+            halted: interpreter.is_halted(),
+        };
+
+        (trace, result)
+    }
+
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+
+    /// The codel the interpreter was at when the trace ended.
+    pub fn end(&self) -> Coords {
+        self.end
+    }
+
+    /// Whether the trace ended because the program halted.
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::{Color, Hue::*, Lightness::*};
+    use crate::program::Program;
+
+    #[test]
+    fn test_trace_record() {
+        let lr = Color::Composite(Red, Light);
+        let nr = Color::Composite(Red, Normal);
+        let program = Program::new((4, 1), vec![lr, Color::White, Color::White, nr]);
+
+        let mut interpreter = Interpreter::new(program);
+        let (trace, result) = Trace::record(&mut interpreter, Some(3));
+        result.unwrap();
+
+        assert_eq!(trace.steps().len(), 3);
+        assert!(!trace.halted());
+
+        let first = &trace.steps()[0];
+        assert_eq!(first.exit, (0, 0));
+        assert_eq!(first.to, (3, 0));
+        assert_eq!(first.command, None);
+        assert_eq!(first.slide, 2);
+
+        // Blocked on the right edge, so it switches the CC, turns the DP down,
+        // switches the CC again, and then turns the DP to face left.
+        let second = &trace.steps()[1];
+        assert_eq!(second.retries, 4);
+        assert_eq!(second.dp, DirectionPointer::Left);
+    }
+
+    #[test]
+    fn test_trace_record_error() {
+        use crate::dialect::Dialect;
+
+        let nr = Color::Composite(Red, Normal);
+        let program = Program::new((2, 1), vec![Color::White, nr]);
+
+        let mut interpreter = Interpreter::new(program);
+        interpreter.set_dialect(Dialect::STRICT);
+        let (trace, result) = Trace::record(&mut interpreter, None);
+
+        assert!(trace.steps().is_empty());
+        assert_eq!(trace.end(), (0, 0));
+        match result {
+            Err(Error(ErrorKind::StartOnWhite, _)) => {}
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use crate::cfg::Cfg;
+use crate::command::Command;
+use crate::ir::{Ir, Next, Op};
+use crate::machine::Machine;
+use crate::program::Program;
+use crate::stack::Stack;
+
+/// The move out of a state, with everything about it worked out ahead of
+/// time. Targets are positions in the bytecode.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Instruction {
+    /// There's no way out, so the program halts.
+    Halt,
+    /// Move without executing anything.
+    Jump(u32),
+    /// Push a value, then move.
+    Push(i64, u32),
+    /// Execute a command, then move.
+    Execute(Command, u32),
+    /// Execute `Pointer` or `Switch`, then move to the target for its result.
+    Branch(Command, [u32; 4]),
+}
+
+/// Runs a program compiled to bytecode, with one instruction for every state
+/// in its control flow graph.
+///
+/// This behaves exactly like `Interpreter`, and counts steps the same way, but
+/// it never looks at the image after compiling it, so it's much faster.
+#[derive(Debug, Clone)]
+pub struct Vm {
+    code: Vec<Instruction>,
+    machine: Machine,
+    pc: usize,
+    steps: u64,
+    halted: bool,
+}
+
+impl Vm {
+    pub fn new(program: &Program) -> Self {
+        // Before it's optimized, the IR has a node for every state, with at
+        // most one operation, so each node is exactly one step.
+        let ir = Ir::new(&Cfg::new(program));
+
+        let code = ir
+            .nodes()
+            .iter()
+            .map(|node| match (node.ops(), node.next()) {
+                (_, Next::Halt) => Instruction::Halt,
+                ([], &Next::Jump(next)) => Instruction::Jump(next as u32),
+                (&[Op::Push(value)], &Next::Jump(next)) => Instruction::Push(value, next as u32),
+                (&[Op::Execute(command)], &Next::Jump(next)) => {
+                    Instruction::Execute(command, next as u32)
+                }
+                (_, Next::Branch(command, targets)) => {
+                    let mut code = [0; 4];
+                    for (code, &target) in code.iter_mut().zip(targets) {
+                        *code = target as u32;
+                    }
+
+                    Instruction::Branch(*command, code)
+                }
+                // Unoptimized nodes never do more than that.
+                _ => unreachable!(),
+            })
+            .collect();
+
+        Vm {
+            code,
+            machine: Machine::new(),
+            pc: 0,
+            steps: 0,
+            halted: false,
+        }
+    }
+
+    /// Append text to the input that `IntIn` and `CharIn` read from.
+    pub fn feed_input(&mut self, input: &str) {
+        self.machine.feed_input(input);
+    }
+
+    /// Whether the next step reads input, but there's none left that it could
+    /// read, so more should be fed first.
+    pub fn needs_input(&self) -> bool {
+        match self.code[self.pc] {
+            Instruction::Execute(command, _) => !self.halted && self.machine.needs_input(command),
+            _ => false,
+        }
+    }
+
+    pub fn stack(&self) -> &Stack {
+        self.machine.stack()
+    }
+
+    /// Everything the program has written so far.
+    pub fn output(&self) -> &str {
+        self.machine.output()
+    }
+
+    /// The number of steps taken so far, counted the same way as
+    /// `Interpreter::steps`.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Take one step.
+    ///
+    /// # Returns
+    ///
+    /// `false` once the program has halted.
+    pub fn step(&mut self) -> bool {
+        self.run(Some(self.steps + 1));
+
+        !self.halted
+    }
+
+    /// Run until the program halts, or until it has taken `max_steps` steps in
+    /// total.
+    pub fn run(&mut self, max_steps: Option<u64>) {
+        let max_steps = max_steps.unwrap_or(u64::MAX);
+
+        while !self.halted && self.steps < max_steps {
+            self.pc = match self.code[self.pc] {
+                Instruction::Halt => {
+                    self.halted = true;
+                    break;
+                }
+                Instruction::Jump(next) => next,
+                Instruction::Push(value, next) => {
+                    self.machine.push(value);
+                    next
+                }
+                Instruction::Execute(command, next) => {
+                    self.machine.execute(command, 1);
+                    next
+                }
+                Instruction::Branch(command, targets) => {
+                    targets[self.machine.execute(command, 1) as usize]
+                }
+            } as usize;
+
+            self.steps += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::differential::{self, INPUT};
+    use crate::interpreter::Interpreter;
+
+    /// Run a program with both the interpreter and the VM, checking that they
+    /// agree after every step.
+    fn assert_same_behavior(program: &Program, input: &str) {
+        let mut interpreter = Interpreter::new(program.clone());
+        let mut vm = Vm::new(program);
+        interpreter.feed_input(input);
+        vm.feed_input(input);
+
+        for _ in 0..5_000 {
+            assert_eq!(vm.needs_input(), interpreter.needs_input());
+            let running = interpreter.step().is_some();
+            assert_eq!(vm.step(), running);
+            assert_eq!(vm.steps(), interpreter.steps());
+            assert_eq!(vm.stack(), interpreter.stack());
+            assert_eq!(vm.output(), interpreter.output());
+
+            if !running {
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn test_vm() {
+        for program in &differential::command_programs() {
+            assert_same_behavior(program, INPUT);
+            assert_same_behavior(program, "");
+        }
+
+        for program in &differential::random_programs(100) {
+            assert_same_behavior(program, INPUT);
+        }
+
+        let mut vm = Vm::new(&Program::new((0, 0), vec![]));
+        assert!(!vm.step());
+        assert_eq!(vm.steps(), 0);
+    }
+
+    #[test]
+    fn test_vm_max_steps() {
+        let program = &differential::command_programs()[0];
+        let mut vm = Vm::new(program);
+
+        vm.run(Some(3));
+        assert_eq!(vm.steps(), 3);
+        assert!(!vm.is_halted());
+
+        vm.run(None);
+        assert!(vm.is_halted());
+        assert_eq!(
+            Some(vm.output()),
+            differential::interpret(program, "").as_deref()
+        );
+    }
+}